@@ -0,0 +1,278 @@
+use crate::delay::TripDelay;
+use crate::gtfs_realtime::TripUpdate;
+use crate::gtfs_realtime::TripUpdate_StopTimeUpdate;
+use crate::schedule::service_day_start;
+use crate::vehicle::Vehicle;
+
+use gtfs_structures::Gtfs;
+use gtfs_structures::Stop;
+use gtfs_structures::StopTime;
+use gtfs_structures::Trip;
+
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Europe::Brussels;
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+// A trip that is only in the realtime feed, so all we know about it are the
+// predicted times at its stops.
+#[derive(Debug, Clone)]
+pub struct AddedTrip {
+    pub id: String,
+    pub route_id: String,
+    pub start_date: Option<NaiveDate>,
+    pub stop_times: Vec<AddedStopTime>,
+    // The position of the train, if the feed has one
+    pub vehicle: Option<Vehicle>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AddedStopTime {
+    pub stop_id: String,
+    // In seconds since the epoch
+    pub arrival_time: Option<i64>,
+    pub departure_time: Option<i64>,
+}
+
+impl From<TripUpdate_StopTimeUpdate> for AddedStopTime {
+    fn from(update: TripUpdate_StopTimeUpdate) -> AddedStopTime {
+        AddedStopTime {
+            stop_id: update.get_stop_id().to_string(),
+            // An event can also only give the delay, which is of no use
+            // without a schedule
+            arrival_time: update.arrival.into_option().filter(|x| x.has_time()).map(|x| x.get_time()),
+            departure_time: update.departure.into_option().filter(|x| x.has_time()).map(|x| x.get_time()),
+        }
+    }
+}
+
+impl From<TripUpdate> for AddedTrip {
+    fn from(update: TripUpdate) -> AddedTrip {
+        let trip = update.get_trip();
+        AddedTrip {
+            id: trip.get_trip_id().to_string(),
+            route_id: trip.get_route_id().to_string(),
+            start_date: NaiveDate::parse_from_str(trip.get_start_date(), "%Y%m%d").ok(),
+            stop_times: update.stop_time_update
+                .into_iter()
+                .map(|stop_time_update| stop_time_update.into())
+                .collect(),
+            vehicle: None,
+        }
+    }
+}
+
+impl AddedTrip {
+    // The first and last known times, stops without a time are skipped
+    fn first_time(&self) -> Option<i64> {
+        self.stop_times
+            .iter()
+            .find_map(|stop_time| stop_time.departure_time.or(stop_time.arrival_time))
+    }
+
+    fn last_time(&self) -> Option<i64> {
+        self.stop_times
+            .iter()
+            .rev()
+            .find_map(|stop_time| stop_time.arrival_time.or(stop_time.departure_time))
+    }
+
+    pub fn rides_now(&self, now: DateTime<Utc>) -> bool {
+        match (self.first_time(), self.last_time()) {
+            (Some(first), Some(last)) => first <= now.timestamp() && last >= now.timestamp(),
+            _ => false,
+        }
+    }
+
+    // Converts the trip to a static trip, so it can be handled like any other.
+    // Stops that are not in the static feed only get their id, times before
+    // the service day are dropped. None if none of the stops has a time.
+    pub fn to_trip(&self, gtfs: &Gtfs) -> Option<Trip> {
+        let first_time = self.first_time()?;
+        let service_date = self.start_date
+            .unwrap_or_else(|| Brussels.timestamp(first_time, 0).date().naive_local());
+        let day_start = service_day_start(service_date).timestamp();
+        let offset = |time: i64| u32::try_from(time - day_start).ok();
+
+        let stop_times: Vec<StopTime> = self.stop_times
+            .iter()
+            .map(|added_stop_time| StopTime {
+                stop: gtfs.stops
+                    .get(&added_stop_time.stop_id)
+                    .cloned()
+                    .unwrap_or_else(|| Arc::new(Stop {
+                        id: added_stop_time.stop_id.clone(),
+                        ..Default::default()
+                    })),
+                arrival_time: added_stop_time.arrival_time.and_then(offset),
+                departure_time: added_stop_time.departure_time.and_then(offset),
+                ..Default::default()
+            })
+            .collect();
+
+        if stop_times.iter().all(|x| x.arrival_time.is_none() && x.departure_time.is_none()) {
+            return None;
+        }
+
+        Some(Trip {
+            id: self.id.clone(),
+            route_id: self.route_id.clone(),
+            trip_headsign: stop_times
+                .last()
+                .map(|stop_time| stop_time.stop.name.clone())
+                .filter(|name| !name.is_empty()),
+            stop_times,
+            ..Default::default()
+        })
+    }
+
+    // The realtime data to build the train with. The times are already in
+    // the trip itself, so only the vehicle is left.
+    pub fn trip_delay(&self) -> TripDelay {
+        TripDelay {
+            start_date: self.start_date,
+            vehicle: self.vehicle.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs_realtime::TripDescriptor;
+    use crate::gtfs_realtime::TripUpdate_StopTimeEvent;
+
+    use super::*;
+
+    #[test]
+    fn test_from_trip_update() {
+        let added = create_added_trip();
+        assert_eq!(added.id, "extra");
+        assert_eq!(added.route_id, "ic-route");
+        assert_eq!(added.start_date, Some(NaiveDate::from_ymd(2022, 6, 19)));
+        assert_eq!(added.stop_times.len(), 2);
+        assert_eq!(added.stop_times[0].stop_id, "A");
+        assert_eq!(added.stop_times[0].arrival_time, None);
+        assert_eq!(added.stop_times[0].departure_time, Some(brussels_timestamp(13, 0)));
+    }
+
+    #[test]
+    fn test_rides_now() {
+        let added = create_added_trip();
+        assert!(!added.rides_now(Utc.timestamp(brussels_timestamp(12, 59), 0)));
+        assert!(added.rides_now(Utc.timestamp(brussels_timestamp(13, 15), 0)));
+        assert!(!added.rides_now(Utc.timestamp(brussels_timestamp(13, 31), 0)));
+    }
+
+    #[test]
+    fn test_rides_now_without_first_time() {
+        let mut added = create_added_trip();
+        added.stop_times.insert(0, AddedStopTime {
+            stop_id: "Z".to_string(),
+            arrival_time: None,
+            departure_time: None,
+        });
+        added.stop_times.push(AddedStopTime {
+            stop_id: "C".to_string(),
+            arrival_time: None,
+            departure_time: None,
+        });
+        assert!(added.rides_now(Utc.timestamp(brussels_timestamp(13, 15), 0)));
+        assert!(!added.rides_now(Utc.timestamp(brussels_timestamp(13, 31), 0)));
+    }
+
+    #[test]
+    fn test_to_trip() {
+        let mut gtfs = Gtfs::default();
+        let mut stop = Stop::default();
+        stop.id = "B".to_string();
+        stop.name = "Station B".to_string();
+        gtfs.stops.insert(stop.id.clone(), Arc::new(stop));
+
+        let trip = create_added_trip().to_trip(&gtfs).unwrap();
+        assert_eq!(trip.id, "extra");
+        assert_eq!(trip.route_id, "ic-route");
+        assert_eq!(trip.trip_headsign, Some("Station B".to_string()));
+        assert_eq!(trip.stop_times[0].stop.id, "A");
+        assert_eq!(trip.stop_times[0].departure_time, Some(13 * 60 * 60));
+        assert_eq!(trip.stop_times[1].stop.name, "Station B");
+        assert_eq!(trip.stop_times[1].arrival_time, Some(13 * 60 * 60 + 30 * 60));
+    }
+
+    #[test]
+    fn test_only_delay() {
+        let mut update = create_stop_time_update("A", None, None);
+        let mut event = TripUpdate_StopTimeEvent::new();
+        event.set_delay(60);
+        update.set_departure(event);
+
+        let stop_time: AddedStopTime = update.into();
+        assert_eq!(stop_time.departure_time, None);
+    }
+
+    #[test]
+    fn test_to_trip_unusable() {
+        let gtfs = Gtfs::default();
+
+        let mut added = create_added_trip();
+        added.stop_times.clear();
+        assert!(added.to_trip(&gtfs).is_none());
+
+        // Times before the service day are dropped
+        let mut added = create_added_trip();
+        added.stop_times[0].departure_time = Some(brussels_timestamp(0, 0) - 60 * 60);
+        let trip = added.to_trip(&gtfs).unwrap();
+        assert_eq!(trip.stop_times[0].departure_time, None);
+        assert_eq!(trip.stop_times[1].arrival_time, Some(13 * 60 * 60 + 30 * 60));
+
+        added.stop_times[1].arrival_time = None;
+        assert!(added.to_trip(&gtfs).is_none());
+    }
+
+    // ----- HELPERS -----
+    fn brussels_timestamp(hour: u32, minute: u32) -> i64 {
+        Brussels.ymd(2022, 6, 19).and_hms(hour, minute, 0).timestamp()
+    }
+
+    fn create_stop_time_update(
+        stop_id: &str,
+        arrival_time: Option<i64>,
+        departure_time: Option<i64>,
+    ) -> TripUpdate_StopTimeUpdate {
+        let mut update = TripUpdate_StopTimeUpdate::new();
+        update.set_stop_id(stop_id.to_string());
+        if let Some(time) = arrival_time {
+            let mut event = TripUpdate_StopTimeEvent::new();
+            event.set_time(time);
+            update.set_arrival(event);
+        }
+        if let Some(time) = departure_time {
+            let mut event = TripUpdate_StopTimeEvent::new();
+            event.set_time(time);
+            update.set_departure(event);
+        }
+        update
+    }
+
+    fn create_added_trip() -> AddedTrip {
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("extra".to_string());
+        trip.set_route_id("ic-route".to_string());
+        trip.set_start_date("20220619".to_string());
+
+        let mut update = TripUpdate::new();
+        update.set_trip(trip);
+        update.mut_stop_time_update().push(
+            create_stop_time_update("A", None, Some(brussels_timestamp(13, 0))),
+        );
+        update.mut_stop_time_update().push(
+            create_stop_time_update("B", Some(brussels_timestamp(13, 30)), None),
+        );
+
+        update.into()
+    }
+}
@@ -0,0 +1,169 @@
+use crate::feed_source::FeedSource;
+
+use serde::Deserialize;
+
+use std::env;
+use std::fs;
+
+const DEFAULT_STATIC_FEED: &str =
+    "https://sncb-opendata.hafas.de/gtfs/static/c21ac6758dd25af84cca5b707f3cb3de";
+const DEFAULT_REALTIME_FEED: &str =
+    "https://sncb-opendata.hafas.de/gtfs/realtime/c21ac6758dd25af84cca5b707f3cb3de";
+
+// Seconds between two downloads of the static feed
+const DEFAULT_STATIC_INTERVAL: u64 = 24 * 60 * 60;
+
+// Number of times loading the static feed is tried before giving up
+const DEFAULT_STATIC_RETRIES: u32 = 5;
+
+// Seconds waited after the first failed load, doubled after every retry
+const DEFAULT_STATIC_RETRY_BACKOFF: u64 = 60;
+
+// Seconds between two downloads of the realtime feed
+const DEFAULT_REALTIME_INTERVAL: u64 = 30;
+
+// Seconds after which realtime data is dropped when it can't be updated
+const DEFAULT_REALTIME_MAX_AGE: u64 = 5 * 60;
+
+// Seconds between two downloads of the works, used to link them to trains
+const DEFAULT_WORKS_INTERVAL: u64 = 5 * 60;
+
+// Path of the config file, used when DELAYMAPI_CONFIG is not set
+const DEFAULT_CONFIG_PATH: &str = "delaymapi.json";
+
+// Configuration is read from a JSON file, after which every field can be
+// overridden using an environment variable. Missing fields keep their default.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub static_feed: String,
+    pub static_interval: u64,
+    pub static_retries: u32,
+    pub static_retry_backoff: u64,
+    pub realtime_feed: String,
+    pub realtime_interval: u64,
+    pub realtime_max_age: u64,
+    pub works_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            static_feed: DEFAULT_STATIC_FEED.to_string(),
+            static_interval: DEFAULT_STATIC_INTERVAL,
+            static_retries: DEFAULT_STATIC_RETRIES,
+            static_retry_backoff: DEFAULT_STATIC_RETRY_BACKOFF,
+            realtime_feed: DEFAULT_REALTIME_FEED.to_string(),
+            realtime_interval: DEFAULT_REALTIME_INTERVAL,
+            realtime_max_age: DEFAULT_REALTIME_MAX_AGE,
+            works_interval: DEFAULT_WORKS_INTERVAL,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let path = env::var("DELAYMAPI_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .unwrap_or_else(|e| panic!("Invalid config file {}: {}", path, e)),
+            Err(_) => Config::default(),
+        };
+
+        config.apply_overrides(|key| env::var(key).ok());
+        config
+    }
+
+    fn apply_overrides<F>(&mut self, lookup: F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(value) = lookup("DELAYMAPI_STATIC_FEED") {
+            self.static_feed = value;
+        }
+        if let Some(value) = lookup("DELAYMAPI_STATIC_INTERVAL") {
+            self.static_interval = value
+                .parse()
+                .expect("Invalid DELAYMAPI_STATIC_INTERVAL");
+        }
+        if let Some(value) = lookup("DELAYMAPI_STATIC_RETRIES") {
+            self.static_retries = value
+                .parse()
+                .expect("Invalid DELAYMAPI_STATIC_RETRIES");
+        }
+        if let Some(value) = lookup("DELAYMAPI_STATIC_RETRY_BACKOFF") {
+            self.static_retry_backoff = value
+                .parse()
+                .expect("Invalid DELAYMAPI_STATIC_RETRY_BACKOFF");
+        }
+        if let Some(value) = lookup("DELAYMAPI_REALTIME_FEED") {
+            self.realtime_feed = value;
+        }
+        if let Some(value) = lookup("DELAYMAPI_REALTIME_INTERVAL") {
+            self.realtime_interval = value
+                .parse()
+                .expect("Invalid DELAYMAPI_REALTIME_INTERVAL");
+        }
+        if let Some(value) = lookup("DELAYMAPI_REALTIME_MAX_AGE") {
+            self.realtime_max_age = value
+                .parse()
+                .expect("Invalid DELAYMAPI_REALTIME_MAX_AGE");
+        }
+        if let Some(value) = lookup("DELAYMAPI_WORKS_INTERVAL") {
+            self.works_interval = value
+                .parse()
+                .expect("Invalid DELAYMAPI_WORKS_INTERVAL");
+        }
+    }
+
+    pub fn static_source(&self) -> FeedSource {
+        FeedSource::parse(&self.static_feed)
+    }
+
+    pub fn realtime_source(&self) -> FeedSource {
+        FeedSource::parse(&self.realtime_feed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_default() {
+        let config = Config::default();
+        assert_eq!(config.static_source(), FeedSource::Url(DEFAULT_STATIC_FEED.to_string()));
+        assert_eq!(config.realtime_source(), FeedSource::Url(DEFAULT_REALTIME_FEED.to_string()));
+    }
+
+    #[test]
+    fn test_partial_file() {
+        let config: Config = serde_json::from_str("{\"static_feed\": \"file:///data/gtfs.zip\"}")
+            .unwrap();
+        assert_eq!(config.static_source(), FeedSource::Path(PathBuf::from("/data/gtfs.zip")));
+        assert_eq!(config.realtime_feed, DEFAULT_REALTIME_FEED.to_string());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut env = HashMap::new();
+        env.insert("DELAYMAPI_REALTIME_FEED", "fixtures/realtime.pb".to_string());
+        env.insert("DELAYMAPI_REALTIME_INTERVAL", "10".to_string());
+        env.insert("DELAYMAPI_STATIC_RETRIES", "2".to_string());
+        env.insert("DELAYMAPI_WORKS_INTERVAL", "60".to_string());
+
+        let mut config = Config::default();
+        config.apply_overrides(|key| env.get(key).cloned());
+
+        assert_eq!(config.static_feed, DEFAULT_STATIC_FEED.to_string());
+        assert_eq!(config.realtime_source(), FeedSource::Path(PathBuf::from("fixtures/realtime.pb")));
+        assert_eq!(config.realtime_interval, 10);
+        assert_eq!(config.static_retries, 2);
+        assert_eq!(config.static_interval, DEFAULT_STATIC_INTERVAL);
+        assert_eq!(config.works_interval, 60);
+    }
+}
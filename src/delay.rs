@@ -1,23 +1,184 @@
+use crate::delaymap_occupancy::occupancy_status;
+use crate::gtfs_realtime::TripDescriptor_ScheduleRelationship;
+use crate::gtfs_realtime::TripUpdate;
 use crate::gtfs_realtime::TripUpdate_StopTimeUpdate;
+use crate::gtfs_realtime::TripUpdate_StopTimeUpdate_ScheduleRelationship;
+use crate::vehicle::Vehicle;
 
-#[derive(Debug, Clone)]
+use gtfs_structures::StopTime;
+use gtfs_structures::Trip;
+
+use chrono::NaiveDate;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
 pub struct Delay {
     pub arrival_delay: Option<i32>,
     pub departure_delay: Option<i32>,
+    // The predicted times, in seconds since the epoch. Only used when the
+    // feed does not give the delay itself.
+    pub arrival_time: Option<i64>,
+    pub departure_time: Option<i64>,
+    // The train passes the stop without stopping
+    pub skipped: bool,
+    // How full the train is expected to be when leaving the stop
+    pub departure_occupancy: Option<String>,
 }
 
 impl From<TripUpdate_StopTimeUpdate> for Delay {
     fn from(update: TripUpdate_StopTimeUpdate) -> Delay {
+        let arrival = update.arrival.as_ref();
+        let departure = update.departure.as_ref();
+
+        // An event without delay or time means the train is on time
+        Delay {
+            skipped: update.get_schedule_relationship()
+                == TripUpdate_StopTimeUpdate_ScheduleRelationship::SKIPPED,
+            departure_delay: departure
+                .filter(|x| x.has_delay() || !x.has_time())
+                .map(|x| x.get_delay()),
+            arrival_delay: arrival
+                .filter(|x| x.has_delay() || !x.has_time())
+                .map(|x| x.get_delay()),
+            departure_time: departure.filter(|x| x.has_time()).map(|x| x.get_time()),
+            arrival_time: arrival.filter(|x| x.has_time()).map(|x| x.get_time()),
+            departure_occupancy: if update.has_departure_occupancy_status() {
+                occupancy_status(update.get_departure_occupancy_status())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Delay {
+    // Derives the missing delays from the predicted times, compared to the
+    // scheduled times of the service day starting at day_start.
+    pub fn resolve(&self, stop_time: &StopTime, day_start: i64) -> Delay {
+        let derive = |time: Option<i64>, scheduled: Option<u32>| {
+            Some((time? - day_start - i64::from(scheduled?)) as i32)
+        };
+
         Delay {
-            departure_delay: update.departure.into_option().map(|x| x.get_delay()),
-            arrival_delay: update.arrival.into_option().map(|x| x.get_delay()),
+            arrival_delay: self.arrival_delay
+                .or_else(|| derive(self.arrival_time, stop_time.arrival_time)),
+            departure_delay: self.departure_delay
+                .or_else(|| derive(self.departure_time, stop_time.departure_time)),
+            ..self.clone()
+        }
+    }
+}
+
+// The realtime updates of a single trip. Updates are keyed by their stop
+// sequence, as a stop can be visited more than once. Updates without a stop
+// sequence are keyed by stop id instead.
+#[derive(Debug, Clone, Default)]
+pub struct TripDelay {
+    pub cancelled: bool,
+    // The service day of the trip, if the feed tells which one it is
+    pub start_date: Option<NaiveDate>,
+    pub sequences: HashMap<u32, Delay>,
+    pub stops: HashMap<String, Delay>,
+    // The position of the train, if the feed has one
+    pub vehicle: Option<Vehicle>,
+}
+
+impl TripDelay {
+    pub fn get(&self, stop_time: &StopTime) -> Option<&Delay> {
+        self.sequences
+            .get(&u32::from(stop_time.stop_sequence))
+            .or_else(|| self.stops.get(&stop_time.stop.id))
+    }
+}
+
+impl From<TripUpdate> for TripDelay {
+    fn from(update: TripUpdate) -> TripDelay {
+        let cancelled = update.get_trip().get_schedule_relationship()
+            == TripDescriptor_ScheduleRelationship::CANCELED;
+        let start_date =
+            NaiveDate::parse_from_str(update.get_trip().get_start_date(), "%Y%m%d").ok();
+
+        let mut sequences = HashMap::new();
+        let mut stops = HashMap::new();
+        for stop_time_update in update.stop_time_update {
+            if stop_time_update.has_stop_sequence() {
+                let stop_sequence = stop_time_update.get_stop_sequence();
+                sequences.insert(stop_sequence, stop_time_update.into());
+            } else {
+                let stop_id = stop_time_update.get_stop_id().to_string();
+                stops.insert(stop_id, stop_time_update.into());
+            }
+        }
+
+        TripDelay {
+            cancelled,
+            start_date,
+            sequences,
+            stops,
+            vehicle: None,
         }
     }
 }
 
+// The delays at every stop of the trip. The last known delay carries over to
+// the stops after it, as the feed only gives the stops where it changes.
+pub fn stop_delays(trip: &Trip, trip_delay: Option<&TripDelay>, day_start: i64) -> Vec<Delay> {
+    let mut curr_delay = Delay {
+        arrival_delay: None,
+        departure_delay: Some(0),
+        arrival_time: None,
+        departure_time: None,
+        skipped: false,
+        departure_occupancy: None,
+    };
+    let last_stop_i = trip.stop_times.len().saturating_sub(1);
+
+    trip.stop_times
+        .iter()
+        .enumerate()
+        .map(|(i, stop_time)| {
+            // Apply delay patch
+            let delay_patch = trip_delay
+                .and_then(|x| x.get(stop_time))
+                .map(|x| x.resolve(stop_time, day_start));
+            if let Some(delay_patch) = &delay_patch {
+                curr_delay.arrival_delay =
+                    delay_patch.arrival_delay.or(curr_delay.arrival_delay);
+                curr_delay.departure_delay =
+                    delay_patch.departure_delay.or(curr_delay.departure_delay);
+            }
+
+            // Unlike the delays, skipping a stop and the occupancy do not
+            // carry over to the next stops
+            curr_delay.skipped = delay_patch.as_ref().map(|x| x.skipped).unwrap_or(false);
+            curr_delay.departure_occupancy =
+                delay_patch.as_ref().and_then(|x| x.departure_occupancy.clone());
+
+            // Make sure delays are not None where they shouldn't be
+            if i != 0 && curr_delay.arrival_delay.is_none() {
+                curr_delay.arrival_delay = Some(0);
+            }
+
+            // Having a departure delay at the final stop makes no sense
+            if i == last_stop_i {
+                curr_delay.departure_delay = None;
+            }
+
+            curr_delay.clone()
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::gtfs_realtime::TripDescriptor;
     use crate::gtfs_realtime::TripUpdate_StopTimeEvent;
+    use crate::gtfs_realtime::VehiclePosition_OccupancyStatus;
+
+    use gtfs_structures::Stop;
+
+    use std::sync::Arc;
 
     use super::*;
 
@@ -27,6 +188,24 @@ mod tests {
         let delay = Delay::from(update);
         assert_eq!(delay.arrival_delay, None);
         assert_eq!(delay.departure_delay, None);
+        assert!(!delay.skipped);
+        assert_eq!(delay.departure_occupancy, None);
+    }
+
+    #[test]
+    fn test_from_occupancy() {
+        let mut update = TripUpdate_StopTimeUpdate::new();
+        update.set_departure_occupancy_status(VehiclePosition_OccupancyStatus::FULL);
+        let delay = Delay::from(update);
+        assert_eq!(delay.departure_occupancy, Some("full".to_string()));
+    }
+
+    #[test]
+    fn test_from_skipped() {
+        let mut update = TripUpdate_StopTimeUpdate::new();
+        update.set_schedule_relationship(TripUpdate_StopTimeUpdate_ScheduleRelationship::SKIPPED);
+        let delay = Delay::from(update);
+        assert!(delay.skipped);
     }
 
     #[test]
@@ -42,4 +221,145 @@ mod tests {
         assert_eq!(delay.arrival_delay, Some(0));
         assert_eq!(delay.departure_delay, Some(25));
     }
+
+    #[test]
+    fn test_from_time() {
+        let mut update = TripUpdate_StopTimeUpdate::new();
+        let mut arrival = TripUpdate_StopTimeEvent::new();
+        arrival.set_time(1655647260);
+        update.set_arrival(arrival);
+        let mut departure = TripUpdate_StopTimeEvent::new();
+        departure.set_time(1655647380);
+        departure.set_delay(120);
+        update.set_departure(departure);
+
+        let delay = Delay::from(update);
+        assert_eq!(delay.arrival_delay, None);
+        assert_eq!(delay.arrival_time, Some(1655647260));
+        assert_eq!(delay.departure_delay, Some(120));
+        assert_eq!(delay.departure_time, Some(1655647380));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut stop_time = StopTime::default();
+        stop_time.arrival_time = Some(100);
+        stop_time.departure_time = Some(200);
+
+        let delay = Delay {
+            arrival_time: Some(1000 + 160),
+            departure_delay: Some(30),
+            departure_time: Some(1000 + 500),
+            ..Default::default()
+        };
+
+        let resolved = delay.resolve(&stop_time, 1000);
+        assert_eq!(resolved.arrival_delay, Some(60));
+        // The delay given by the feed is preferred over the time
+        assert_eq!(resolved.departure_delay, Some(30));
+    }
+
+    #[test]
+    fn test_resolve_nothing() {
+        let resolved = Delay::default().resolve(&StopTime::default(), 1000);
+        assert_eq!(resolved.arrival_delay, None);
+        assert_eq!(resolved.departure_delay, None);
+    }
+
+    #[test]
+    fn test_trip_delay() {
+        let mut trip = TripDescriptor::new();
+        trip.set_start_date("20220619".to_string());
+        let mut update = TripUpdate::new();
+        update.set_trip(trip);
+        let mut stop_time_update = TripUpdate_StopTimeUpdate::new();
+        stop_time_update.set_stop_id("stop1".to_string());
+        update.mut_stop_time_update().push(stop_time_update);
+
+        let trip_delay = TripDelay::from(update);
+        assert!(!trip_delay.cancelled);
+        assert_eq!(trip_delay.start_date, Some(NaiveDate::from_ymd(2022, 6, 19)));
+        assert!(trip_delay.stops.contains_key("stop1"));
+    }
+
+    #[test]
+    fn test_trip_delay_sequence() {
+        let mut update = TripUpdate::new();
+        update.set_trip(TripDescriptor::new());
+        let mut stop_time_update = TripUpdate_StopTimeUpdate::new();
+        stop_time_update.set_stop_id("stop1".to_string());
+        stop_time_update.set_stop_sequence(3);
+        update.mut_stop_time_update().push(stop_time_update);
+
+        let trip_delay = TripDelay::from(update);
+        assert!(trip_delay.sequences.contains_key(&3));
+        assert!(trip_delay.stops.is_empty());
+    }
+
+    #[test]
+    fn test_trip_delay_get() {
+        let mut trip_delay = TripDelay::default();
+        trip_delay.sequences.insert(3, Delay {
+            arrival_delay: Some(30),
+            ..Default::default()
+        });
+        trip_delay.stops.insert("stop1".to_string(), Delay {
+            arrival_delay: Some(10),
+            ..Default::default()
+        });
+
+        let mut stop = Stop::default();
+        stop.id = "stop1".to_string();
+        let mut stop_time = StopTime::default();
+        stop_time.stop = Arc::new(stop);
+
+        stop_time.stop_sequence = 3;
+        assert_eq!(trip_delay.get(&stop_time).unwrap().arrival_delay, Some(30));
+        stop_time.stop_sequence = 1;
+        assert_eq!(trip_delay.get(&stop_time).unwrap().arrival_delay, Some(10));
+    }
+
+    #[test]
+    fn test_trip_delay_cancelled() {
+        let mut update = TripUpdate::new();
+        let mut trip = TripDescriptor::new();
+        trip.set_schedule_relationship(TripDescriptor_ScheduleRelationship::CANCELED);
+        update.set_trip(trip);
+
+        let trip_delay = TripDelay::from(update);
+        assert!(trip_delay.cancelled);
+        assert!(trip_delay.stops.is_empty());
+    }
+
+    #[test]
+    fn test_stop_delays() {
+        let mut trip = Trip::default();
+        for id in &["stop1", "stop2", "stop3"] {
+            let mut stop = Stop::default();
+            stop.id = id.to_string();
+            let mut stop_time = StopTime::default();
+            stop_time.stop = Arc::new(stop);
+            trip.stop_times.push(stop_time);
+        }
+        let mut trip_delay = TripDelay::default();
+        trip_delay.stops.insert("stop2".to_string(), Delay {
+            arrival_delay: Some(60),
+            departure_delay: Some(90),
+            skipped: true,
+            ..Default::default()
+        });
+
+        let delays = stop_delays(&trip, Some(&trip_delay), 1000);
+        assert_eq!(delays[0].arrival_delay, None);
+        assert_eq!(delays[0].departure_delay, Some(0));
+        assert_eq!(delays[1].arrival_delay, Some(60));
+        assert!(delays[1].skipped);
+        // The delay carries over, but skipping the stop does not
+        assert_eq!(delays[2].arrival_delay, Some(60));
+        assert_eq!(delays[2].departure_delay, None);
+        assert!(!delays[2].skipped);
+
+        let scheduled = stop_delays(&trip, None, 1000);
+        assert_eq!(scheduled[2].arrival_delay, Some(0));
+    }
 }
@@ -0,0 +1,266 @@
+use crate::delaymap_stop::DelayMapStop;
+use crate::gtfs_realtime::Alert;
+use crate::gtfs_realtime::EntitySelector;
+use crate::gtfs_realtime::TranslatedString;
+use crate::realtime::enum_name;
+
+use gtfs_structures::Gtfs;
+use gtfs_structures::Translatable;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapAlert {
+    pub id: String,
+    pub cause: String,
+    pub effect: String,
+    pub severity: String,
+    pub active_periods: Vec<DelayMapAlertPeriod>,
+    pub header: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub informed_entities: Vec<DelayMapInformedEntity>,
+}
+
+// In seconds since the epoch, a missing start or end means it is open-ended
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapAlertPeriod {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+// The part of the network an alert is about. The names and stop are only
+// filled in if the ids are known in the static feed.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapInformedEntity {
+    pub agency_id: Option<String>,
+    pub route_id: Option<String>,
+    pub route_name: Option<String>,
+    pub trip_id: Option<String>,
+    pub trip_name: Option<String>,
+    pub stop_id: Option<String>,
+    pub stop: Option<DelayMapStop>,
+}
+
+// Picks the translation in the given language, falling back to the one
+// without language and then to any translation at all.
+fn translate(text: &TranslatedString, language: &str) -> Option<String> {
+    let translations = text.get_translation();
+    translations
+        .iter()
+        .find(|translation| translation.get_language().split('-').next() == Some(language))
+        .or_else(|| translations.iter().find(|translation| !translation.has_language()))
+        .or_else(|| translations.first())
+        .map(|translation| translation.get_text().to_string())
+}
+
+fn optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+impl DelayMapInformedEntity {
+    pub fn from_gtfs(gtfs: &Gtfs, selector: &EntitySelector, language: &str) -> Self {
+        let trip_id = if selector.has_trip() {
+            optional(selector.get_trip().get_trip_id())
+        } else {
+            None
+        };
+        let trip = trip_id.as_ref().and_then(|id| gtfs.trips.get(id));
+
+        // The route can also be derived from the trip
+        let route_id = optional(selector.get_route_id())
+            .or_else(|| trip.map(|trip| trip.route_id.clone()));
+        let route_name = route_id
+            .as_ref()
+            .and_then(|id| gtfs.routes.get(id))
+            .map(|route| {
+                if route.short_name.is_empty() {
+                    route.long_name.clone()
+                } else {
+                    route.short_name.clone()
+                }
+            });
+
+        let stop_id = optional(selector.get_stop_id());
+        let stop = stop_id
+            .as_ref()
+            .and_then(|id| gtfs.get_stop_translated(id, language).ok())
+            .map(|stop| stop.into());
+
+        DelayMapInformedEntity {
+            agency_id: optional(selector.get_agency_id()),
+            route_id,
+            route_name,
+            trip_name: trip.and_then(|trip| trip.translate(gtfs, language).trip_headsign),
+            trip_id,
+            stop_id,
+            stop,
+        }
+    }
+}
+
+impl DelayMapAlert {
+    pub fn from_gtfs(gtfs: &Gtfs, id: &str, alert: &Alert, language: &str) -> Self {
+        let text = |has_text: bool, text: &TranslatedString| {
+            if has_text {
+                translate(text, language)
+            } else {
+                None
+            }
+        };
+
+        DelayMapAlert {
+            id: id.to_string(),
+            cause: enum_name(alert.get_cause()),
+            effect: enum_name(alert.get_effect()),
+            severity: enum_name(alert.get_severity_level()),
+            active_periods: alert
+                .get_active_period()
+                .iter()
+                .map(|period| DelayMapAlertPeriod {
+                    start: if period.has_start() { Some(period.get_start()) } else { None },
+                    end: if period.has_end() { Some(period.get_end()) } else { None },
+                })
+                .collect(),
+            header: text(alert.has_header_text(), alert.get_header_text()),
+            description: text(alert.has_description_text(), alert.get_description_text()),
+            url: text(alert.has_url(), alert.get_url()),
+            informed_entities: alert
+                .get_informed_entity()
+                .iter()
+                .map(|selector| DelayMapInformedEntity::from_gtfs(gtfs, selector, language))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs_realtime::Alert_Cause;
+    use crate::gtfs_realtime::Alert_Effect;
+    use crate::gtfs_realtime::TimeRange;
+    use crate::gtfs_realtime::TranslatedString_Translation;
+    use crate::gtfs_realtime::TripDescriptor;
+
+    use gtfs_structures::Route;
+    use gtfs_structures::Stop;
+    use gtfs_structures::Trip;
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        let text = create_text(&[("", "Default"), ("nl", "Nederlands"), ("fr-BE", "Français")]);
+        assert_eq!(translate(&text, "nl"), Some("Nederlands".to_string()));
+        assert_eq!(translate(&text, "fr"), Some("Français".to_string()));
+        assert_eq!(translate(&text, "de"), Some("Default".to_string()));
+
+        let text = create_text(&[("nl", "Nederlands")]);
+        assert_eq!(translate(&text, "en"), Some("Nederlands".to_string()));
+        assert_eq!(translate(&TranslatedString::new(), "en"), None);
+    }
+
+    #[test]
+    fn test_from_gtfs() {
+        let gtfs = create_gtfs();
+        let mut alert = Alert::new();
+        alert.set_cause(Alert_Cause::STRIKE);
+        alert.set_effect(Alert_Effect::NO_SERVICE);
+        alert.set_header_text(create_text(&[("en", "Strike"), ("nl", "Staking")]));
+        let mut period = TimeRange::new();
+        period.set_start(1655647200);
+        alert.mut_active_period().push(period);
+
+        let delaymap_alert = DelayMapAlert::from_gtfs(&gtfs, "alert1", &alert, "nl");
+
+        assert_eq!(delaymap_alert.id, "alert1");
+        assert_eq!(delaymap_alert.cause, "strike");
+        assert_eq!(delaymap_alert.effect, "noService");
+        assert_eq!(delaymap_alert.severity, "unknownSeverity");
+        assert_eq!(delaymap_alert.header, Some("Staking".to_string()));
+        assert_eq!(delaymap_alert.description, None);
+        assert_eq!(delaymap_alert.active_periods, vec![DelayMapAlertPeriod {
+            start: Some(1655647200),
+            end: None,
+        }]);
+    }
+
+    #[test]
+    fn test_informed_entity() {
+        let gtfs = create_gtfs();
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("ic".to_string());
+        let mut selector = EntitySelector::new();
+        selector.set_trip(trip);
+        selector.set_stop_id("A".to_string());
+
+        let entity = DelayMapInformedEntity::from_gtfs(&gtfs, &selector, "en");
+
+        assert_eq!(entity.trip_id, Some("ic".to_string()));
+        assert_eq!(entity.trip_name, Some("To Oostende".to_string()));
+        assert_eq!(entity.route_id, Some("ic-route".to_string()));
+        assert_eq!(entity.route_name, Some("IC".to_string()));
+        assert_eq!(entity.stop.unwrap().name, "Station A");
+    }
+
+    #[test]
+    fn test_informed_entity_unknown() {
+        let gtfs = create_gtfs();
+        let mut selector = EntitySelector::new();
+        selector.set_route_id("unknown".to_string());
+        selector.set_stop_id("unknown".to_string());
+
+        let entity = DelayMapInformedEntity::from_gtfs(&gtfs, &selector, "en");
+
+        assert_eq!(entity.route_id, Some("unknown".to_string()));
+        assert_eq!(entity.route_name, None);
+        assert_eq!(entity.stop_id, Some("unknown".to_string()));
+        assert!(entity.stop.is_none());
+        assert_eq!(entity.trip_id, None);
+    }
+
+    // ----- HELPERS -----
+    fn create_text(translations: &[(&str, &str)]) -> TranslatedString {
+        let mut text = TranslatedString::new();
+        for (language, value) in translations {
+            let mut translation = TranslatedString_Translation::new();
+            translation.set_text(value.to_string());
+            if !language.is_empty() {
+                translation.set_language(language.to_string());
+            }
+            text.mut_translation().push(translation);
+        }
+        text
+    }
+
+    fn create_gtfs() -> Gtfs {
+        let mut gtfs = Gtfs::default();
+
+        let mut stop = Stop::default();
+        stop.id = "A".to_string();
+        stop.name = "Station A".to_string();
+        gtfs.stops.insert(stop.id.clone(), Arc::new(stop));
+
+        let mut route = Route::default();
+        route.id = "ic-route".to_string();
+        route.short_name = "IC".to_string();
+        gtfs.routes.insert(route.id.clone(), route);
+
+        let mut trip = Trip::default();
+        trip.id = "ic".to_string();
+        trip.route_id = "ic-route".to_string();
+        trip.trip_headsign = Some("To Oostende".to_string());
+        gtfs.trips.insert(trip.id.clone(), trip);
+
+        gtfs
+    }
+}
@@ -0,0 +1,140 @@
+use crate::delaymap_geometry::DelayMapGeometry;
+
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response;
+use rocket::response::content::Content;
+use rocket::response::Responder;
+use rocket_contrib::json::Json;
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum DelayMapGeoJson {
+    // The properties are the fields of the item as they are in the JSON format
+    Feature {
+        geometry: Option<DelayMapGeometry>,
+        properties: Value,
+    },
+    FeatureCollection {
+        features: Vec<DelayMapGeoJson>,
+    },
+}
+
+impl DelayMapGeoJson {
+    // A collection of points at the location of each item. Items without a
+    // location are included without a geometry.
+    pub fn collection<T: Serialize>(
+        items: &[T],
+        location: impl Fn(&T) -> Option<(f64, f64)>,
+    ) -> Self {
+        DelayMapGeoJson::FeatureCollection {
+            features: items
+                .iter()
+                .map(|item| DelayMapGeoJson::Feature {
+                    geometry: location(item).map(|(lat, lon)| DelayMapGeometry::point(lat, lon)),
+                    properties: serde_json::to_value(item).unwrap_or(Value::Null),
+                })
+                .collect(),
+        }
+    }
+}
+
+// A list in the format given by the format url parameter: geojson, or json by
+// default
+pub enum DelayMapFormatted<T> {
+    Json(Vec<T>),
+    GeoJson(DelayMapGeoJson),
+}
+
+impl<T: Serialize> DelayMapFormatted<T> {
+    pub fn new(
+        items: Vec<T>,
+        format: Option<String>,
+        location: impl Fn(&T) -> Option<(f64, f64)>,
+    ) -> Self {
+        match format.as_deref() {
+            Some("geojson") => DelayMapFormatted::GeoJson(DelayMapGeoJson::collection(&items, location)),
+            _ => DelayMapFormatted::Json(items),
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r> for DelayMapFormatted<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            DelayMapFormatted::Json(items) => Json(items).respond_to(request),
+            DelayMapFormatted::GeoJson(geojson) => {
+                Content(ContentType::new("application", "geo+json"), Json(geojson))
+                    .respond_to(request)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_collection() {
+        let places = vec![create_place("Gent", Some((51.0, 3.5))), create_place("Nowhere", None)];
+        let geojson = DelayMapGeoJson::collection(&places, |place| place.location);
+
+        assert_eq!(
+            serde_json::to_value(geojson).unwrap(),
+            json!({
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "Point", "coordinates": [3.5, 51.0] },
+                        "properties": { "name": "Gent", "location": [51.0, 3.5] },
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": null,
+                        "properties": { "name": "Nowhere", "location": null },
+                    },
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_formatted() {
+        let places = vec![create_place("Gent", Some((51.0, 3.5)))];
+        let location = |place: &Place| place.location;
+
+        assert!(matches!(
+            DelayMapFormatted::new(places.clone(), None, location),
+            DelayMapFormatted::Json(_)
+        ));
+        assert!(matches!(
+            DelayMapFormatted::new(places.clone(), Some("json".to_string()), location),
+            DelayMapFormatted::Json(_)
+        ));
+        assert!(matches!(
+            DelayMapFormatted::new(places, Some("geojson".to_string()), location),
+            DelayMapFormatted::GeoJson(_)
+        ));
+    }
+
+    // ----- HELPERS -----
+    #[derive(Serialize, Clone)]
+    struct Place {
+        name: String,
+        location: Option<(f64, f64)>,
+    }
+
+    fn create_place(name: &str, location: Option<(f64, f64)>) -> Place {
+        Place {
+            name: name.to_string(),
+            location,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::delaymap_train::DelayMapTrain;
+use crate::shape::ShapeLine;
+
+use serde::Serialize;
+
+// A GeoJSON geometry, with the coordinates as [longitude, latitude]
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum DelayMapGeometry {
+    Point { coordinates: [f64; 2] },
+    LineString { coordinates: Vec<[f64; 2]> },
+}
+
+impl DelayMapGeometry {
+    pub fn point(lat: f64, lon: f64) -> Self {
+        DelayMapGeometry::Point {
+            coordinates: [lon, lat],
+        }
+    }
+
+    // The points are given as (latitude, longitude)
+    pub fn line(points: &[(f64, f64)]) -> Self {
+        DelayMapGeometry::LineString {
+            coordinates: points.iter().map(|(lat, lon)| [*lon, *lat]).collect(),
+        }
+    }
+}
+
+// The route of a train, split at its estimated position
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapTrainGeometry {
+    pub travelled: DelayMapGeometry,
+    pub remaining: DelayMapGeometry,
+}
+
+impl DelayMapTrainGeometry {
+    // Uses the shape of the trip if there is one, otherwise straight lines
+    // between the stops. None if there are not enough stops with a location.
+    pub fn from_train(train: &DelayMapTrain, shape: Option<&ShapeLine>) -> Option<Self> {
+        let stop_line;
+        let line = match shape {
+            Some(shape) => shape,
+            None => {
+                stop_line = ShapeLine::from_points(
+                    train.stops.iter().filter_map(|stop| Some((stop.lat?, stop.lon?))).collect(),
+                )?;
+                &stop_line
+            }
+        };
+
+        // Locate the stops up to the current one in order, so the position is
+        // found on the right part of the line if it passes a place twice
+        let mut from = 0.0;
+        for stop in train.stops.iter().take(train.stop_index) {
+            if let (Some(lat), Some(lon)) = (stop.lat, stop.lon) {
+                from = line.locate((lat, lon), None, from);
+            }
+        }
+        let distance = line.locate((train.estimated_lat, train.estimated_lon), None, from);
+
+        Some(DelayMapTrainGeometry {
+            travelled: DelayMapGeometry::line(&line.slice(0.0, distance)),
+            remaining: DelayMapGeometry::line(&line.slice(distance, line.length())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delaymap_stop_time::DelayMapStopTime;
+
+    use super::*;
+
+    // Current test setup: stops at (0, 0), (0, 1) and (1, 1), the train is
+    // riding between the last two stops at (0.5, 1)
+
+    #[test]
+    fn test_point() {
+        assert_eq!(
+            serde_json::to_value(DelayMapGeometry::point(51.0, 3.5)).unwrap(),
+            serde_json::json!({
+                "type": "Point",
+                "coordinates": [3.5, 51.0],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            serde_json::to_value(DelayMapGeometry::line(&[(51.0, 3.5), (51.5, 4.0)])).unwrap(),
+            serde_json::json!({
+                "type": "LineString",
+                "coordinates": [[3.5, 51.0], [4.0, 51.5]],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_from_stops() {
+        let train = create_train(&[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+        let geometry = DelayMapTrainGeometry::from_train(&train, None).unwrap();
+        assert_line(&geometry.travelled, &[(0.0, 0.0), (0.0, 1.0), (0.5, 1.0)]);
+        assert_line(&geometry.remaining, &[(0.5, 1.0), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_from_shape() {
+        let train = create_train(&[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+        // The track makes a detour after the second stop
+        let shape = ShapeLine::from_points(vec![
+            (0.0, 0.0), (0.0, 1.0), (0.5, 1.0), (0.5, 1.5), (1.0, 1.5), (1.0, 1.0),
+        ]).unwrap();
+        let geometry = DelayMapTrainGeometry::from_train(&train, Some(&shape)).unwrap();
+        assert_line(&geometry.travelled, &[(0.0, 0.0), (0.0, 1.0), (0.5, 1.0)]);
+        assert_line(&geometry.remaining, &[(0.5, 1.0), (0.5, 1.5), (1.0, 1.5), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_loop() {
+        // The train passes (0.5, 1) twice, but has only done so once
+        let train = create_train(&[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.0, 1.0)]);
+        let geometry = DelayMapTrainGeometry::from_train(&train, None).unwrap();
+        assert_line(&geometry.travelled, &[(0.0, 0.0), (0.0, 1.0), (0.5, 1.0)]);
+    }
+
+    #[test]
+    fn test_no_locations() {
+        let train = create_train(&[(0.0, 0.0)]);
+        assert_eq!(DelayMapTrainGeometry::from_train(&train, None), None);
+    }
+
+    // ----- HELPERS -----
+    fn assert_line(actual: &DelayMapGeometry, expected: &[(f64, f64)]) {
+        let coordinates = match actual {
+            DelayMapGeometry::LineString { coordinates } => coordinates,
+            _ => panic!("{:?} is not a line", actual),
+        };
+        assert_eq!(coordinates.len(), expected.len(), "{:?}", coordinates);
+        for (coordinate, (lat, lon)) in coordinates.iter().zip(expected) {
+            assert!(
+                (coordinate[0] - lon).abs() < 1e-9 && (coordinate[1] - lat).abs() < 1e-9,
+                "{:?} != {:?}",
+                coordinates,
+                expected,
+            );
+        }
+    }
+
+    fn create_train(stops: &[(f64, f64)]) -> DelayMapTrain {
+        DelayMapTrain {
+            stops: stops
+                .iter()
+                .enumerate()
+                .map(|(i, (lat, lon))| DelayMapStopTime {
+                    name: format!("Stop {}", i + 1),
+                    lat: Some(*lat),
+                    lon: Some(*lon),
+                    stop_id: format!("stop{}", i + 1),
+                    ..DelayMapStopTime::test_default()
+                })
+                .collect(),
+            stop_index: 2,
+            estimated_lat: 0.5,
+            estimated_lon: 1.0,
+            ..DelayMapTrain::test_default()
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::delay::stop_delays;
+use crate::delay::TripDelay;
+use crate::delaymap_stop::DelayMapStop;
+use crate::delaymap_stop_time::DelayMapStopTime;
+use crate::schedule::rides_at_date;
+use crate::schedule::service_date_at;
+use crate::schedule::service_day_start;
+
+use gtfs_structures::Gtfs;
+use gtfs_structures::Stop;
+use gtfs_structures::Translatable;
+
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Europe::Brussels;
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapLiveboard {
+    pub stop: DelayMapStop,
+    pub departures: Vec<DelayMapLiveboardEntry>,
+    pub arrivals: Vec<DelayMapLiveboardEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapLiveboardEntry {
+    pub trip_id: String,
+    pub headsign: String,
+    pub stop_id: String,
+    pub platform: Option<String>,
+    // In seconds since the epoch
+    pub scheduled_time: i64,
+    // In seconds
+    pub delay: i32,
+    // The trip is cancelled, or does not stop here
+    pub cancelled: bool,
+}
+
+impl DelayMapLiveboardEntry {
+    fn actual_time(&self) -> i64 {
+        self.scheduled_time + i64::from(self.delay)
+    }
+}
+
+impl DelayMapLiveboard {
+    // Lists the trains departing or arriving at the stop, or at any of its
+    // platforms if it is a parent station, between now and now + window.
+    pub fn from_gtfs(
+        gtfs: &Gtfs,
+        stop: Stop,
+        language: &str,
+        delaymap: &HashMap<String, TripDelay>,
+        now: DateTime<Utc>,
+        window: Duration,
+    ) -> DelayMapLiveboard {
+        let mut departures = vec![];
+        let mut arrivals = vec![];
+
+        let local_date = Brussels.from_utc_datetime(&now.naive_utc()).date().naive_local();
+        let window_start = now.timestamp();
+        let window_end = (now + window).timestamp();
+        let in_window = |entry: &DelayMapLiveboardEntry| {
+            entry.actual_time() >= window_start && entry.actual_time() <= window_end
+        };
+
+        for trip in gtfs.trips.values() {
+            let stop_indices: Vec<usize> = trip.stop_times
+                .iter()
+                .enumerate()
+                .filter(|(_, stop_time)| {
+                    stop_time.stop.id == stop.id
+                        || stop_time.stop.parent_station.as_ref() == Some(&stop.id)
+                })
+                .map(|(i, _)| i)
+                .collect();
+
+            if stop_indices.is_empty() {
+                continue;
+            }
+
+            let translated_trip = trip.translate(gtfs, language);
+            // The realtime data is about a single run of the trip
+            let realtime_date = delaymap
+                .get(&trip.id)
+                .and_then(|x| x.start_date)
+                .unwrap_or_else(|| service_date_at(trip, now));
+
+            // Trips of yesterday's service day can still be running after midnight
+            for date in &[local_date.pred(), local_date] {
+                if !rides_at_date(gtfs, trip, *date) {
+                    continue;
+                }
+                let day_start = service_day_start(*date).timestamp();
+
+                // Only the delays at this stop are needed, not the position of
+                // the train. The other run is shown as scheduled.
+                let trip_delay = delaymap.get(&trip.id).filter(|_| *date == realtime_date);
+                let delays = stop_delays(&translated_trip, trip_delay, day_start);
+                let cancelled = trip_delay.map(|x| x.cancelled).unwrap_or(false);
+
+                for &i in &stop_indices {
+                    let stop_time = &translated_trip.stop_times[i];
+                    let delaymap_stop_time = DelayMapStopTime::from_gtfs(stop_time, &delays[i], day_start);
+
+                    let create_entry = |timestamp: u32, delay: i32| DelayMapLiveboardEntry {
+                        trip_id: trip.id.clone(),
+                        headsign: stop_time
+                            .stop_headsign
+                            .clone()
+                            .or_else(|| translated_trip.trip_headsign.clone())
+                            .unwrap_or("Unknown Train".to_string()),
+                        stop_id: stop_time.stop.id.clone(),
+                        platform: stop_time.stop.platform_code.clone(),
+                        scheduled_time: day_start + i64::from(timestamp),
+                        delay,
+                        cancelled: cancelled || delaymap_stop_time.skipped,
+                    };
+
+                    if let (Some(timestamp), Some(delay)) = (
+                        delaymap_stop_time.departure_timestamp,
+                        delaymap_stop_time.departure_delay,
+                    ) {
+                        let entry = create_entry(timestamp, delay);
+                        if in_window(&entry) {
+                            departures.push(entry);
+                        }
+                    }
+
+                    if let (Some(timestamp), Some(delay)) = (
+                        delaymap_stop_time.arrival_timestamp,
+                        delaymap_stop_time.arrival_delay,
+                    ) {
+                        let entry = create_entry(timestamp, delay);
+                        if in_window(&entry) {
+                            arrivals.push(entry);
+                        }
+                    }
+                }
+            }
+        }
+
+        departures.sort_by_key(DelayMapLiveboardEntry::actual_time);
+        arrivals.sort_by_key(DelayMapLiveboardEntry::actual_time);
+
+        DelayMapLiveboard {
+            stop: stop.into(),
+            departures,
+            arrivals,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delay::Delay;
+
+    use gtfs_structures::Calendar;
+    use gtfs_structures::StopTime;
+    use gtfs_structures::Trip;
+
+    use chrono::NaiveDate;
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    // Current test setup, on 2022-06-19 (times in Brussels):
+    // Trip "ic", "To Oostende": Station A (13:00) -> Station B, platform 3
+    //   (13:10-13:12) -> Station C (13:30)
+    // Trip "night", "To Station B": Station C (23:50 the day before) ->
+    //   Station B, platform 4 (00:10)
+
+    #[test]
+    fn test_departures() {
+        let gtfs = create_gtfs();
+        let liveboard = create_liveboard(&gtfs, "B", brussels_time(19, 13, 0), 30, HashMap::new());
+
+        assert_eq!(liveboard.stop.name, "Station B");
+        assert_eq!(liveboard.departures.len(), 1);
+        let departure = &liveboard.departures[0];
+        assert_eq!(departure.trip_id, "ic");
+        assert_eq!(departure.headsign, "To Oostende");
+        assert_eq!(departure.stop_id, "B_3");
+        assert_eq!(departure.platform, Some("3".to_string()));
+        assert_eq!(departure.scheduled_time, brussels_timestamp(19, 13, 12));
+        assert_eq!(departure.delay, 0);
+
+        assert_eq!(liveboard.arrivals.len(), 1);
+        assert_eq!(liveboard.arrivals[0].scheduled_time, brussels_timestamp(19, 13, 10));
+    }
+
+    #[test]
+    fn test_outside_window() {
+        let gtfs = create_gtfs();
+        let liveboard = create_liveboard(&gtfs, "B", brussels_time(19, 14, 0), 60, HashMap::new());
+
+        assert!(liveboard.departures.is_empty());
+        assert!(liveboard.arrivals.is_empty());
+    }
+
+    #[test]
+    fn test_delayed_into_window() {
+        let gtfs = create_gtfs();
+        let mut trip_delay = TripDelay::default();
+        trip_delay.stops.insert("A".to_string(), Delay {
+            arrival_delay: None,
+            departure_delay: Some(10 * 60),
+            ..Default::default()
+        });
+        let mut delaymap = HashMap::new();
+        delaymap.insert("ic".to_string(), trip_delay);
+
+        // Scheduled at 13:12, but leaving at 13:22
+        let liveboard = create_liveboard(&gtfs, "B", brussels_time(19, 13, 15), 10, delaymap);
+
+        assert_eq!(liveboard.departures.len(), 1);
+        assert_eq!(liveboard.departures[0].delay, 10 * 60);
+        assert_eq!(liveboard.departures[0].scheduled_time, brussels_timestamp(19, 13, 12));
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let gtfs = create_gtfs();
+        let mut delaymap = HashMap::new();
+        delaymap.insert("ic".to_string(), TripDelay {
+            cancelled: true,
+            ..Default::default()
+        });
+
+        let liveboard = create_liveboard(&gtfs, "B", brussels_time(19, 13, 0), 30, delaymap);
+
+        assert_eq!(liveboard.departures.len(), 1);
+        assert!(liveboard.departures[0].cancelled);
+        assert!(liveboard.arrivals[0].cancelled);
+    }
+
+    #[test]
+    fn test_skipped() {
+        let gtfs = create_gtfs();
+        let mut trip_delay = TripDelay::default();
+        trip_delay.stops.insert("B_3".to_string(), Delay {
+            skipped: true,
+            ..Default::default()
+        });
+        let mut delaymap = HashMap::new();
+        delaymap.insert("ic".to_string(), trip_delay);
+
+        let skipped = create_liveboard(&gtfs, "B", brussels_time(19, 13, 0), 30, delaymap.clone());
+        assert!(skipped.departures[0].cancelled);
+
+        let not_skipped = create_liveboard(&gtfs, "C", brussels_time(19, 13, 0), 60, delaymap);
+        assert!(!not_skipped.arrivals[0].cancelled);
+    }
+
+    #[test]
+    fn test_first_and_last_stop() {
+        let gtfs = create_gtfs();
+
+        let first = create_liveboard(&gtfs, "A", brussels_time(19, 12, 50), 30, HashMap::new());
+        assert_eq!(first.departures.len(), 1);
+        assert!(first.arrivals.is_empty());
+
+        let last = create_liveboard(&gtfs, "C", brussels_time(19, 13, 20), 30, HashMap::new());
+        assert!(last.departures.is_empty());
+        assert_eq!(last.arrivals.len(), 1);
+    }
+
+    #[test]
+    fn test_after_midnight() {
+        let gtfs = create_gtfs();
+        let liveboard = create_liveboard(
+            &gtfs,
+            "B",
+            brussels_time(20, 0, 0),
+            30,
+            HashMap::new(),
+        );
+
+        assert_eq!(liveboard.arrivals.len(), 1);
+        let arrival = &liveboard.arrivals[0];
+        assert_eq!(arrival.trip_id, "night");
+        assert_eq!(arrival.platform, Some("4".to_string()));
+        assert_eq!(arrival.scheduled_time, brussels_timestamp(20, 0, 10));
+    }
+
+    #[test]
+    fn test_realtime_other_day() {
+        let gtfs = create_gtfs();
+        let mut delaymap = HashMap::new();
+        delaymap.insert("night".to_string(), TripDelay {
+            cancelled: true,
+            start_date: Some(NaiveDate::from_ymd(2022, 6, 19)),
+            ..Default::default()
+        });
+
+        // The run of the 19th is cancelled, the one of the 18th is not
+        let yesterday = create_liveboard(
+            &gtfs,
+            "C",
+            brussels_time(19, 23, 45),
+            10,
+            delaymap.clone(),
+        );
+        assert_eq!(yesterday.departures.len(), 1);
+        assert!(yesterday.departures[0].cancelled);
+
+        let today = create_liveboard(
+            &gtfs,
+            "B",
+            brussels_time(19, 0, 0),
+            30,
+            delaymap,
+        );
+        assert_eq!(today.arrivals.len(), 1);
+        assert_eq!(today.arrivals[0].scheduled_time, brussels_timestamp(19, 0, 10));
+        assert!(!today.arrivals[0].cancelled);
+    }
+
+    // ----- HELPERS -----
+    fn brussels_timestamp(day: u32, hour: u32, minute: u32) -> i64 {
+        Brussels.ymd(2022, 6, day).and_hms(hour, minute, 0).timestamp()
+    }
+
+    fn brussels_time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Brussels.ymd(2022, 6, day).and_hms(hour, minute, 0).with_timezone(&Utc)
+    }
+
+    fn create_liveboard(
+        gtfs: &Gtfs,
+        stop_id: &str,
+        now: DateTime<Utc>,
+        window_minutes: i64,
+        delaymap: HashMap<String, TripDelay>,
+    ) -> DelayMapLiveboard {
+        let stop = gtfs.get_stop(stop_id).unwrap().clone();
+        DelayMapLiveboard::from_gtfs(
+            gtfs,
+            stop,
+            "en",
+            &delaymap,
+            now,
+            Duration::minutes(window_minutes),
+        )
+    }
+
+    fn create_stop(id: &str, name: &str, parent: Option<&str>, platform: Option<&str>) -> Arc<Stop> {
+        let mut stop = Stop::default();
+        stop.id = id.to_string();
+        stop.name = name.to_string();
+        stop.parent_station = parent.map(|x| x.to_string());
+        stop.platform_code = platform.map(|x| x.to_string());
+        Arc::new(stop)
+    }
+
+    fn create_stoptime(stop: &Arc<Stop>, arrival_time: Option<u32>, departure_time: Option<u32>) -> StopTime {
+        let mut stoptime = StopTime::default();
+        stoptime.stop = stop.clone();
+        stoptime.arrival_time = arrival_time;
+        stoptime.departure_time = departure_time;
+        stoptime
+    }
+
+    fn hms(hour: u32, minute: u32) -> Option<u32> {
+        Some(hour * 60 * 60 + minute * 60)
+    }
+
+    fn create_gtfs() -> Gtfs {
+        let mut gtfs = Gtfs::default();
+
+        let stop_a = create_stop("A", "Station A", None, None);
+        let stop_b = create_stop("B", "Station B", None, None);
+        let stop_b3 = create_stop("B_3", "Station B", Some("B"), Some("3"));
+        let stop_b4 = create_stop("B_4", "Station B", Some("B"), Some("4"));
+        let stop_c = create_stop("C", "Station C", None, None);
+        for stop in &[&stop_a, &stop_b, &stop_b3, &stop_b4, &stop_c] {
+            gtfs.stops.insert(stop.id.clone(), (*stop).clone());
+        }
+
+        gtfs.calendar.insert("daily".to_string(), Calendar {
+            id: "daily".to_string(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: true,
+            sunday: true,
+            start_date: NaiveDate::from_ymd(2022, 1, 1),
+            end_date: NaiveDate::from_ymd(2022, 12, 31),
+        });
+
+        let mut ic = Trip::default();
+        ic.id = "ic".to_string();
+        ic.service_id = "daily".to_string();
+        ic.trip_headsign = Some("To Oostende".to_string());
+        ic.stop_times = vec![
+            create_stoptime(&stop_a, None, hms(13, 0)),
+            create_stoptime(&stop_b3, hms(13, 10), hms(13, 12)),
+            create_stoptime(&stop_c, hms(13, 30), None),
+        ];
+        gtfs.trips.insert(ic.id.clone(), ic);
+
+        let mut night = Trip::default();
+        night.id = "night".to_string();
+        night.service_id = "daily".to_string();
+        night.trip_headsign = Some("To Station B".to_string());
+        night.stop_times = vec![
+            create_stoptime(&stop_c, None, hms(23, 50)),
+            create_stoptime(&stop_b4, hms(24, 10), None),
+        ];
+        gtfs.trips.insert(night.id.clone(), night);
+
+        gtfs
+    }
+}
@@ -0,0 +1,147 @@
+use crate::gtfs_realtime::VehiclePosition;
+use crate::gtfs_realtime::VehiclePosition_CarriageDetails;
+use crate::gtfs_realtime::VehiclePosition_OccupancyStatus;
+use crate::realtime::enum_name;
+
+use serde::Serialize;
+
+// The status is the GTFS-realtime occupancy status in camelCase, for example
+// manySeatsAvailable
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapOccupancy {
+    pub status: Option<String>,
+    pub percentage: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapCarriage {
+    pub id: Option<String>,
+    pub label: Option<String>,
+    // 1 for the first carriage in the direction of travel
+    pub sequence: Option<u32>,
+    pub occupancy: Option<DelayMapOccupancy>,
+}
+
+// NO_DATA_AVAILABLE is the same as not having a status at all
+pub fn occupancy_status(status: VehiclePosition_OccupancyStatus) -> Option<String> {
+    if status == VehiclePosition_OccupancyStatus::NO_DATA_AVAILABLE {
+        None
+    } else {
+        Some(enum_name(status))
+    }
+}
+
+impl DelayMapOccupancy {
+    // None if there is no occupancy information at all
+    pub fn new(status: Option<String>, percentage: Option<u32>) -> Option<Self> {
+        if status.is_none() && percentage.is_none() {
+            None
+        } else {
+            Some(DelayMapOccupancy { status, percentage })
+        }
+    }
+
+    pub fn from_vehicle(vehicle_position: &VehiclePosition) -> Option<Self> {
+        DelayMapOccupancy::new(
+            if vehicle_position.has_occupancy_status() {
+                occupancy_status(vehicle_position.get_occupancy_status())
+            } else {
+                None
+            },
+            if vehicle_position.has_occupancy_percentage() {
+                Some(vehicle_position.get_occupancy_percentage())
+            } else {
+                None
+            },
+        )
+    }
+}
+
+impl From<&VehiclePosition_CarriageDetails> for DelayMapCarriage {
+    fn from(carriage: &VehiclePosition_CarriageDetails) -> Self {
+        let optional = |has_value: bool, value: &str| {
+            if has_value {
+                Some(value.to_string())
+            } else {
+                None
+            }
+        };
+
+        DelayMapCarriage {
+            id: optional(carriage.has_id(), carriage.get_id()),
+            label: optional(carriage.has_label(), carriage.get_label()),
+            sequence: if carriage.has_carriage_sequence() {
+                Some(carriage.get_carriage_sequence())
+            } else {
+                None
+            },
+            // Both have a default that means there is no data
+            occupancy: DelayMapOccupancy::new(
+                occupancy_status(carriage.get_occupancy_status()),
+                if carriage.get_occupancy_percentage() >= 0 {
+                    Some(carriage.get_occupancy_percentage() as u32)
+                } else {
+                    None
+                },
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_occupancy_status() {
+        assert_eq!(
+            occupancy_status(VehiclePosition_OccupancyStatus::STANDING_ROOM_ONLY),
+            Some("standingRoomOnly".to_string()),
+        );
+        assert_eq!(occupancy_status(VehiclePosition_OccupancyStatus::NO_DATA_AVAILABLE), None);
+    }
+
+    #[test]
+    fn test_from_vehicle() {
+        assert_eq!(DelayMapOccupancy::from_vehicle(&VehiclePosition::new()), None);
+
+        let mut vehicle_position = VehiclePosition::new();
+        vehicle_position.set_occupancy_status(VehiclePosition_OccupancyStatus::FULL);
+        vehicle_position.set_occupancy_percentage(95);
+        assert_eq!(
+            DelayMapOccupancy::from_vehicle(&vehicle_position),
+            Some(DelayMapOccupancy {
+                status: Some("full".to_string()),
+                percentage: Some(95),
+            }),
+        );
+    }
+
+    #[test]
+    fn test_carriage() {
+        let mut carriage = VehiclePosition_CarriageDetails::new();
+        carriage.set_label("Car 1".to_string());
+        carriage.set_carriage_sequence(1);
+        carriage.set_occupancy_status(VehiclePosition_OccupancyStatus::FEW_SEATS_AVAILABLE);
+
+        let delaymap_carriage = DelayMapCarriage::from(&carriage);
+        assert_eq!(delaymap_carriage.id, None);
+        assert_eq!(delaymap_carriage.label, Some("Car 1".to_string()));
+        assert_eq!(delaymap_carriage.sequence, Some(1));
+        assert_eq!(
+            delaymap_carriage.occupancy,
+            Some(DelayMapOccupancy {
+                status: Some("fewSeatsAvailable".to_string()),
+                percentage: None,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_carriage_no_data() {
+        let delaymap_carriage = DelayMapCarriage::from(&VehiclePosition_CarriageDetails::new());
+        assert_eq!(delaymap_carriage.occupancy, None);
+    }
+}
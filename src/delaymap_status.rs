@@ -0,0 +1,203 @@
+use crate::realtime::RealtimeSnapshot;
+use crate::realtime::RealtimeStatus;
+
+use gtfs_structures::Gtfs;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+
+// Keeps track of how the last attempts to fetch a feed went
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchOutcome {
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub last_attempt: Option<DateTime<Utc>>,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl FetchOutcome {
+    pub fn succeeded(&mut self, now: DateTime<Utc>) {
+        self.last_attempt = Some(now);
+        self.last_success = Some(now);
+        self.last_error = None;
+    }
+
+    pub fn failed<E: Display>(&mut self, error: &E, now: DateTime<Utc>) {
+        self.last_attempt = Some(now);
+        self.last_error = Some(error.to_string());
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapStaticStatus {
+    #[serde(flatten)]
+    pub outcome: FetchOutcome,
+    pub trips: usize,
+    pub stops: usize,
+    pub services: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapRealtimeStatus {
+    #[serde(flatten)]
+    pub outcome: FetchOutcome,
+    pub status: RealtimeStatus,
+    pub feed_timestamp: Option<u64>,
+    pub trips: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayMapStatus {
+    pub static_feed: DelayMapStaticStatus,
+    pub realtime_feed: DelayMapRealtimeStatus,
+    // By language, as each one is downloaded separately
+    pub works: BTreeMap<&'static str, FetchOutcome>,
+}
+
+impl DelayMapStatus {
+    pub fn new(
+        gtfs: &Gtfs,
+        static_outcome: &FetchOutcome,
+        realtime: &RealtimeSnapshot,
+        realtime_outcome: &FetchOutcome,
+        works_outcomes: &BTreeMap<&'static str, FetchOutcome>,
+    ) -> Self {
+        let services: HashSet<&String> = gtfs.calendar
+            .keys()
+            .chain(gtfs.calendar_dates.keys())
+            .collect();
+
+        DelayMapStatus {
+            static_feed: DelayMapStaticStatus {
+                outcome: static_outcome.clone(),
+                trips: gtfs.trips.len(),
+                stops: gtfs.stops.len(),
+                services: services.len(),
+            },
+            realtime_feed: DelayMapRealtimeStatus {
+                outcome: realtime_outcome.clone(),
+                status: realtime.status(),
+                feed_timestamp: realtime.feed_timestamp,
+                trips: realtime.delays.len(),
+            },
+            works: works_outcomes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_structures::CalendarDate;
+    use gtfs_structures::Exception;
+
+    use chrono::NaiveDate;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        let mut outcome = FetchOutcome::default();
+        let first = Utc.ymd(2022, 6, 19).and_hms(14, 0, 0);
+        let second = Utc.ymd(2022, 6, 19).and_hms(15, 0, 0);
+
+        outcome.succeeded(first);
+        assert_eq!(outcome.last_attempt, Some(first));
+        assert_eq!(outcome.last_success, Some(first));
+        assert_eq!(outcome.last_error, None);
+
+        outcome.failed(&"timed out", second);
+        assert_eq!(outcome.last_attempt, Some(second));
+        assert_eq!(outcome.last_success, Some(first));
+        assert_eq!(outcome.last_error, Some("timed out".to_string()));
+    }
+
+    #[test]
+    fn test_outcome_serialize() {
+        let mut outcome = FetchOutcome::default();
+        outcome.failed(&"timed out", Utc.timestamp(1655647200, 0));
+
+        let json = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json["lastAttempt"], 1655647200);
+        assert!(json["lastSuccess"].is_null());
+        assert_eq!(json["lastError"], "timed out");
+    }
+
+    #[test]
+    fn test_status_empty() {
+        let gtfs = Gtfs::default();
+        let outcome = FetchOutcome::default();
+        let status = DelayMapStatus::new(
+            &gtfs,
+            &outcome,
+            &RealtimeSnapshot::empty(),
+            &outcome,
+            &BTreeMap::new(),
+        );
+
+        assert_eq!(status.static_feed.trips, 0);
+        assert_eq!(status.static_feed.stops, 0);
+        assert_eq!(status.static_feed.services, 0);
+        assert_eq!(status.realtime_feed.status, RealtimeStatus::Missing);
+        assert_eq!(status.realtime_feed.feed_timestamp, None);
+        assert!(status.works.is_empty());
+    }
+
+    #[test]
+    fn test_status_works() {
+        let gtfs = Gtfs::default();
+        let now = Utc.ymd(2022, 6, 19).and_hms(14, 0, 0);
+        let mut works_outcomes = BTreeMap::new();
+        works_outcomes.insert("en", FetchOutcome::default());
+        works_outcomes.insert("nl", FetchOutcome::default());
+        works_outcomes.get_mut("en").unwrap().succeeded(now);
+        works_outcomes.get_mut("nl").unwrap().failed(&"timed out", now);
+
+        let outcome = FetchOutcome::default();
+        let status = DelayMapStatus::new(
+            &gtfs,
+            &outcome,
+            &RealtimeSnapshot::empty(),
+            &outcome,
+            &works_outcomes,
+        );
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert!(json["works"]["en"]["lastError"].is_null());
+        assert_eq!(json["works"]["nl"]["lastError"], "timed out");
+    }
+
+    #[test]
+    fn test_status_services() {
+        let mut gtfs = Gtfs::default();
+        for service_id in &["service1", "service2"] {
+            gtfs.calendar_dates.insert(service_id.to_string(), vec![CalendarDate {
+                service_id: service_id.to_string(),
+                date: NaiveDate::from_ymd(2022, 6, 19),
+                exception_type: Exception::Added,
+            }]);
+        }
+
+        let outcome = FetchOutcome::default();
+        let status = DelayMapStatus::new(
+            &gtfs,
+            &outcome,
+            &RealtimeSnapshot::empty(),
+            &outcome,
+            &BTreeMap::new(),
+        );
+
+        assert_eq!(status.static_feed.services, 2);
+    }
+}
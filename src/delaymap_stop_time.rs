@@ -1,4 +1,4 @@
-use crate::Delay;
+use crate::delay::Delay;
 use gtfs_structures::StopTime;
 
 use serde::Serialize;
@@ -15,11 +15,21 @@ pub struct DelayMapStopTime {
     pub arrival_timestamp: Option<u32>,
     pub departure_delay: Option<i32>,
     pub departure_timestamp: Option<u32>,
+    // The predicted times including the delay, in seconds since the epoch
+    pub predicted_arrival: Option<i64>,
+    pub predicted_departure: Option<i64>,
     pub stop_id: String,
+    pub skipped: bool,
+    pub departure_occupancy: Option<String>,
 }
 
 impl DelayMapStopTime {
-    pub fn from_gtfs(stoptime: &StopTime, delay: &Delay) -> DelayMapStopTime {
+    // day_start is the start of the service day, in seconds since the epoch
+    pub fn from_gtfs(stoptime: &StopTime, delay: &Delay, day_start: i64) -> DelayMapStopTime {
+        let predict = |timestamp: Option<u32>, delay: Option<i32>| {
+            Some(day_start + i64::from(timestamp?) + i64::from(delay?))
+        };
+
         DelayMapStopTime {
             name: stoptime.stop.name.clone(),
             lat: stoptime.stop.latitude,
@@ -28,7 +38,32 @@ impl DelayMapStopTime {
             arrival_timestamp: stoptime.arrival_time,
             departure_delay: delay.departure_delay,
             departure_timestamp: stoptime.departure_time,
+            predicted_arrival: predict(stoptime.arrival_time, delay.arrival_delay),
+            predicted_departure: predict(stoptime.departure_time, delay.departure_delay),
             stop_id: stoptime.stop.id.clone(),
+            skipped: delay.skipped,
+            departure_occupancy: delay.departure_occupancy.clone(),
+        }
+    }
+}
+
+// An empty stop time for tests, which only fill in the fields they need
+#[cfg(test)]
+impl DelayMapStopTime {
+    pub fn test_default() -> DelayMapStopTime {
+        DelayMapStopTime {
+            name: String::new(),
+            lat: None,
+            lon: None,
+            arrival_delay: None,
+            arrival_timestamp: None,
+            departure_delay: None,
+            departure_timestamp: None,
+            predicted_arrival: None,
+            predicted_departure: None,
+            stop_id: String::new(),
+            skipped: false,
+            departure_occupancy: None,
         }
     }
 }
@@ -55,8 +90,11 @@ mod tests {
         let delay = Delay {
             arrival_delay: Some(12),
             departure_delay: Some(34),
+            skipped: true,
+            departure_occupancy: Some("full".to_string()),
+            ..Default::default()
         };
-        let dm_stoptime = DelayMapStopTime::from_gtfs(&stoptime, &delay);
+        let dm_stoptime = DelayMapStopTime::from_gtfs(&stoptime, &delay, 1000);
 
         assert_eq!(dm_stoptime.name, "Stop 1");
         assert_eq!(dm_stoptime.lat, Some(13.37));
@@ -65,7 +103,11 @@ mod tests {
         assert_eq!(dm_stoptime.arrival_timestamp, Some(123));
         assert_eq!(dm_stoptime.departure_delay, Some(34));
         assert_eq!(dm_stoptime.departure_timestamp, Some(456));
+        assert_eq!(dm_stoptime.predicted_arrival, Some(1000 + 123 + 12));
+        assert_eq!(dm_stoptime.predicted_departure, Some(1000 + 456 + 34));
         assert_eq!(dm_stoptime.stop_id, "stop1_id");
+        assert!(dm_stoptime.skipped);
+        assert_eq!(dm_stoptime.departure_occupancy, Some("full".to_string()));
     }
 
     #[test]
@@ -74,8 +116,10 @@ mod tests {
         let delay = Delay {
             arrival_delay: None,
             departure_delay: None,
+            skipped: false,
+            ..Default::default()
         };
-        let dm_stoptime = DelayMapStopTime::from_gtfs(&stoptime, &delay);
+        let dm_stoptime = DelayMapStopTime::from_gtfs(&stoptime, &delay, 1000);
 
         assert_eq!(dm_stoptime.name, "");
         assert_eq!(dm_stoptime.lat, None);
@@ -84,6 +128,10 @@ mod tests {
         assert_eq!(dm_stoptime.arrival_timestamp, None);
         assert_eq!(dm_stoptime.departure_delay, None);
         assert_eq!(dm_stoptime.departure_timestamp, None);
+        assert_eq!(dm_stoptime.predicted_arrival, None);
+        assert_eq!(dm_stoptime.predicted_departure, None);
         assert_eq!(dm_stoptime.stop_id, "");
+        assert!(!dm_stoptime.skipped);
+        assert_eq!(dm_stoptime.departure_occupancy, None);
     }
 }
@@ -1,17 +1,32 @@
+use crate::delaymap_geometry::DelayMapTrainGeometry;
+use crate::delaymap_occupancy::DelayMapCarriage;
+use crate::delaymap_occupancy::DelayMapOccupancy;
 use crate::delaymap_stop_time::DelayMapStopTime;
-use crate::delay::Delay;
+use crate::delaymap_works::DelayMapWorks;
+use crate::delay::stop_delays;
+use crate::delay::TripDelay;
+use crate::schedule::service_date_at;
+use crate::schedule::service_day_start;
+use crate::shape::ShapeLine;
 
 use gtfs_structures::Trip;
 
 use std::collections::HashMap;
 
-use chrono::Timelike;
-use chrono::TimeZone;
+use chrono::DateTime;
 use chrono::Utc;
-use chrono_tz::Europe::Brussels;
 
 use serde::Serialize;
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PositionSource {
+    // Interpolated between the stops, using the schedule and delays
+    Interpolated,
+    // Reported by the train itself in the realtime feed
+    Vehicle,
+}
+
 #[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DelayMapTrain {
@@ -22,13 +37,46 @@ pub struct DelayMapTrain {
     pub is_stopped: bool,
     pub estimated_lat: f64,
     pub estimated_lon: f64,
+    // In degrees, clockwise from north
+    pub bearing: Option<f32>,
+    // In meters per second
+    pub speed: Option<f32>,
+    pub position_source: PositionSource,
+    pub occupancy: Option<DelayMapOccupancy>,
+    pub carriages: Vec<DelayMapCarriage>,
+    pub cancelled: bool,
+    // Whether the trip is on the road, otherwise its scheduled state is shown
+    pub riding: bool,
+    // Only filled in when asked for
+    pub geometry: Option<DelayMapTrainGeometry>,
+    // The ids of the active works on the rest of the trip
+    pub affecting_works: Vec<String>,
 }
 
 impl DelayMapTrain {
+    // Estimates where the train is at the given moment. If the shape of the
+    // trip is known, the train follows it between stops instead of riding in
+    // a straight line.
     pub fn from_gtfs(
         trip: &Trip,
-        delaymap: &HashMap<String, HashMap<String, Delay>>
+        delaymap: &HashMap<String, TripDelay>,
+        shape: Option<&ShapeLine>,
+        now: DateTime<Utc>,
+    ) -> DelayMapTrain {
+        DelayMapTrain::from_trip_delay(trip, delaymap.get(&trip.id), shape, now)
+    }
+
+    // Same as from_gtfs, with the realtime data of the trip given directly
+    pub fn from_trip_delay(
+        trip: &Trip,
+        trip_delay: Option<&TripDelay>,
+        shape: Option<&ShapeLine>,
+        now: DateTime<Utc>,
     ) -> DelayMapTrain {
+        let service_date = trip_delay
+            .and_then(|x| x.start_date)
+            .unwrap_or_else(|| service_date_at(trip, now));
+        let day_start = service_day_start(service_date).timestamp();
         let mut ret = DelayMapTrain {
             id: trip.id.to_string(),
             name: trip
@@ -40,43 +88,47 @@ impl DelayMapTrain {
             is_stopped: false,
             estimated_lat: 0.0,
             estimated_lon: 0.0,
+            bearing: None,
+            speed: None,
+            position_source: PositionSource::Interpolated,
+            occupancy: None,
+            carriages: vec![],
+            cancelled: trip_delay.map(|x| x.cancelled).unwrap_or(false),
+            riding: true,
+            geometry: None,
+            affecting_works: vec![],
         };
 
-        let mut curr_delay = Delay {
-            arrival_delay: None,
-            departure_delay: Some(0),
-        };
-
-        let local_datetime = Brussels.from_utc_datetime(&Utc::now().naive_utc());
-        let local_timestamp = local_datetime.time().num_seconds_from_midnight() as i64;
+        // Like the stop times, relative to the start of the service day. For
+        // runs of yesterday's service day, this is more than 24 hours.
+        let service_timestamp = now.timestamp() - day_start;
 
         let mut previous_departure = 0;
         let mut previous_stop_lat = 0.0;
         let mut previous_stop_lon = 0.0;
+        // The distance along the shape of the previous stop
+        let mut previous_distance = 0.0;
         let last_stop_i = trip.stop_times.len() - 1;
 
-        for (i, stop_time) in trip.stop_times.iter().enumerate() {
-            // Apply delay patch
-            if let Some(trip_delaymap) = delaymap.get(&trip.id) {
-                if let Some(delay_patch) = trip_delaymap.get(&stop_time.stop.id) {
-                    curr_delay.arrival_delay =
-                        delay_patch.arrival_delay.or(curr_delay.arrival_delay);
-                    curr_delay.departure_delay =
-                        delay_patch.departure_delay.or(curr_delay.departure_delay);
-                }
-            }
-
-            // Make sure delays are not None where they shouldn't be
-            if i != 0 && curr_delay.arrival_delay.is_none() {
-                curr_delay.arrival_delay = Some(0);
-            }
+        let delays = stop_delays(trip, trip_delay, day_start);
+        for (i, (stop_time, curr_delay)) in trip.stop_times.iter().zip(&delays).enumerate() {
+            let stop = DelayMapStopTime::from_gtfs(&stop_time, curr_delay, day_start);
 
-            // Having a departure delay at the final stop makes no sense
-            if i == last_stop_i {
-                curr_delay.departure_delay = None;
+            // The train rides through skipped stops, so its position is
+            // interpolated between the stops around it
+            if stop.skipped {
+                ret.stops.push(stop);
+                continue;
             }
 
-            let stop = DelayMapStopTime::from_gtfs(&stop_time, &curr_delay);
+            let distance = match (shape, stop.lat, stop.lon) {
+                (Some(shape), Some(lat), Some(lon)) => Some(shape.locate(
+                    (lat, lon),
+                    stop_time.shape_dist_traveled,
+                    previous_distance,
+                )),
+                _ => None,
+            };
 
             // Calculate arrival and departure time, using dummy values for
             // start and end station. Not inteded to be used in the API
@@ -97,29 +149,38 @@ impl DelayMapTrain {
 
             // If the train has not left the station and either already arrived,
             // or is still at its first station, it is stopped at that station.
-            if actual_departure > local_timestamp && (i == 0 || actual_arrival < local_timestamp) {
+            if actual_departure > service_timestamp && (i == 0 || actual_arrival < service_timestamp) {
                 ret.stop_index = i;
                 ret.is_stopped = true;
                 ret.estimated_lat = stop.lat.unwrap_or(0.0);
                 ret.estimated_lon = stop.lon.unwrap_or(0.0);
             // If the train have left the previous station, but has not arrived
             // at the next station, it is riding between these two stations.
-            } else if actual_arrival > local_timestamp && previous_departure < local_timestamp {
+            } else if actual_arrival > service_timestamp && previous_departure < service_timestamp {
                 ret.stop_index = i;
                 ret.is_stopped = false;
                 let curr_stop_lat = stop.lat.unwrap_or(0.0);
                 let curr_stop_lon = stop.lon.unwrap_or(0.0);
 
-                // Linearly interpolate between the two coordinates.
-                let percentage_complete: f64 = ((local_timestamp - previous_departure) as f64)
+                let percentage_complete: f64 = ((service_timestamp - previous_departure) as f64)
                     / (actual_arrival - previous_departure) as f64;
-                ret.estimated_lat = percentage_complete * curr_stop_lat
-                    + (1.0 - percentage_complete) * previous_stop_lat;
-                ret.estimated_lon = percentage_complete * curr_stop_lon
-                    + (1.0 - percentage_complete) * previous_stop_lon;
+                if let (Some(shape), Some(distance)) = (shape, distance) {
+                    // Move along the shape, proportionally to the distance
+                    let (lat, lon) = shape.point_at(
+                        previous_distance + percentage_complete * (distance - previous_distance),
+                    );
+                    ret.estimated_lat = lat;
+                    ret.estimated_lon = lon;
+                } else {
+                    // Linearly interpolate between the two coordinates.
+                    ret.estimated_lat = percentage_complete * curr_stop_lat
+                        + (1.0 - percentage_complete) * previous_stop_lat;
+                    ret.estimated_lon = percentage_complete * curr_stop_lon
+                        + (1.0 - percentage_complete) * previous_stop_lon;
+                }
             // If the train has arrived at the final station, it is still at
             // that station
-        } else if actual_arrival < local_timestamp && i == last_stop_i {
+        } else if actual_arrival < service_timestamp && i == last_stop_i {
                 ret.stop_index = i;
                 ret.is_stopped = true;
                 ret.estimated_lat = stop.lat.unwrap_or(0.0);
@@ -129,21 +190,109 @@ impl DelayMapTrain {
             previous_departure = actual_departure;
             previous_stop_lat = stop.lat.unwrap_or(0.0);
             previous_stop_lon = stop.lon.unwrap_or(0.0);
+            previous_distance = distance.unwrap_or(previous_distance);
             ret.stops.push(stop);
         }
+
+        // Outdated vehicle data, or data of another moment, is not used at all
+        let vehicle = trip_delay
+            .and_then(|x| x.vehicle.as_ref())
+            .filter(|x| x.is_recent(now));
+        if let Some(vehicle) = vehicle {
+            ret.occupancy = vehicle.occupancy.clone();
+            ret.carriages = vehicle.carriages.clone();
+
+            // A recent position reported by the train is better than our estimate
+            if let Some((lat, lon)) = vehicle.position_at(now) {
+                ret.estimated_lat = lat;
+                ret.estimated_lon = lon;
+                ret.bearing = vehicle.bearing;
+                ret.speed = vehicle.speed;
+                ret.position_source = PositionSource::Vehicle;
+                if let Some(stop_index) = vehicle.stop_index(&trip.stop_times) {
+                    ret.stop_index = stop_index;
+                    ret.is_stopped = vehicle.stopped;
+                }
+            }
+        }
+
         ret
     }
+
+    // Adds the route of the train, using the same shape as from_gtfs
+    pub fn with_geometry(mut self, shape: Option<&ShapeLine>) -> DelayMapTrain {
+        self.geometry = DelayMapTrainGeometry::from_train(&self, shape);
+        self
+    }
+
+    // Links the works that affect the rest of the trip
+    pub fn with_works(mut self, trip: &Trip, works: &[DelayMapWorks], now: DateTime<Utc>) -> DelayMapTrain {
+        self.affecting_works = works
+            .iter()
+            .filter(|works| works.affects(trip, &self, now))
+            .map(|works| works.id.clone())
+            .collect();
+        self
+    }
+
+    // The delay at the stop the train is at, or is riding towards
+    pub fn current_delay(&self) -> i32 {
+        self.stops
+            .get(self.stop_index)
+            .and_then(|stop| {
+                if self.is_stopped {
+                    stop.departure_delay.or(stop.arrival_delay)
+                } else {
+                    stop.arrival_delay
+                }
+            })
+            .unwrap_or(0)
+    }
+}
+
+// An empty train for tests, which only fill in the fields they need
+#[cfg(test)]
+impl DelayMapTrain {
+    pub fn test_default() -> DelayMapTrain {
+        DelayMapTrain {
+            id: "my-train".to_string(),
+            name: "My Train".to_string(),
+            stops: vec![],
+            stop_index: 0,
+            is_stopped: false,
+            estimated_lat: 0.0,
+            estimated_lon: 0.0,
+            bearing: None,
+            speed: None,
+            position_source: PositionSource::Interpolated,
+            occupancy: None,
+            carriages: vec![],
+            cancelled: false,
+            riding: true,
+            geometry: None,
+            affecting_works: vec![],
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{sync::Arc, convert::TryInto};
 
-    use gtfs_structures::{StopTime, Stop};
+    use gtfs_structures::{Shape, StopTime, Stop};
+
+    use crate::delay::Delay;
+    use crate::vehicle::Vehicle;
+
+    use chrono::Duration;
+    use chrono::NaiveDate;
+    use chrono::Timelike;
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Brussels;
 
     use super::*;
 
-    // Current test setup:
+    // Current test setup, with the time being 12:00 in Brussels:
     // name, time of arrival-departure (relative), coordinates
     // Stop 1: none-0:00, (0, 0)
     // Stop 2: 1:00-1:05, (3, 3)
@@ -155,7 +304,7 @@ mod tests {
     fn test_trip_id_name() {
         let trip = create_trip(0);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.name, "My Train".to_string());
         assert_eq!(train.id, "my-train".to_string());
     }
@@ -165,7 +314,7 @@ mod tests {
     fn test_interpolation_not_started() {
         let trip = create_trip(-10);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.estimated_lat, 0.0);
         assert_eq!(train.estimated_lon, 0.0);
         assert_eq!(train.is_stopped, true);
@@ -176,7 +325,7 @@ mod tests {
     fn test_interpolation_first_sector() {
         let trip = create_trip(40);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.estimated_lat, 2.0);
         assert_eq!(train.estimated_lon, 2.0);
         assert_eq!(train.is_stopped, false);
@@ -187,7 +336,7 @@ mod tests {
     fn test_interpolation_in_station() {
         let trip = create_trip(62);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.estimated_lat, 3.0);
         assert_eq!(train.estimated_lon, 3.0);
         assert_eq!(train.is_stopped, true);
@@ -198,7 +347,7 @@ mod tests {
     fn test_interpolation_last_sector() {
         let trip = create_trip(150);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.estimated_lat, 4.2);
         assert_eq!(train.estimated_lon, 6.0);
         assert_eq!(train.is_stopped, false);
@@ -209,19 +358,84 @@ mod tests {
     fn test_interpolation_arrived() {
         let trip = create_trip(200);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
         assert_eq!(train.estimated_lat, 6.0);
         assert_eq!(train.estimated_lon, 6.0);
         assert_eq!(train.is_stopped, true);
         assert_eq!(train.stop_index, 3);
     }
 
+    #[test]
+    fn test_interpolation_after_midnight() {
+        // Yesterday's run, from 23:50 to 00:30
+        let mut trip = create_trip(0);
+        trip.stop_times = vec![
+            create_stoptime("stop1", "Stop 1", Some(0.0), Some(0.0), None, Some(hms(23, 50))),
+            create_stoptime("stop2", "Stop 2", Some(3.0), Some(3.0), Some(hms(24, 10)), Some(hms(24, 15))),
+            create_stoptime("stop3", "Stop 3", Some(3.0), Some(6.0), Some(hms(24, 30)), None),
+        ];
+        let now = Brussels.ymd(2022, 6, 20).and_hms(0, 5, 0).with_timezone(&Utc);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), None, now);
+        assert_eq!(train.estimated_lat, 2.25);
+        assert_eq!(train.estimated_lon, 2.25);
+        assert_eq!(train.is_stopped, false);
+        assert_eq!(train.stop_index, 1);
+
+        let now = Brussels.ymd(2022, 6, 20).and_hms(0, 12, 0).with_timezone(&Utc);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), None, now);
+        assert_eq!(train.is_stopped, true);
+        assert_eq!(train.stop_index, 1);
+    }
+
+    // ----- CURRENT DELAY TESTS -----
+    #[test]
+    fn test_current_delay_riding() {
+        let trip = create_trip(80);
+        let mut trip_delays = HashMap::new();
+
+        trip_delays.insert("stop2".to_string(), Delay {
+            arrival_delay: Some(60),
+            departure_delay: Some(30),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+        assert_eq!(train.current_delay(), 60);
+    }
+
+    #[test]
+    fn test_current_delay_stopped() {
+        let trip = create_trip(-10);
+        let mut trip_delays = HashMap::new();
+
+        trip_delays.insert("stop1".to_string(), Delay {
+            arrival_delay: None,
+            departure_delay: Some(120),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+        assert_eq!(train.current_delay(), 120);
+    }
+
     // ----- DELAY TESTS-----
     #[test]
     fn test_delay_none() {
         let trip = create_trip(0);
         let delaymap = HashMap::new();
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
 
         check_delays(
             train,
@@ -239,6 +453,7 @@ mod tests {
         trip_delays.insert("stop2".to_string(), Delay {
             arrival_delay: Some(2),
             departure_delay: Some(1),
+            ..Default::default()
         });
 
         // NOTE: NMBS does not do this, they always provide none or both, but
@@ -248,12 +463,16 @@ mod tests {
         trip_delays.insert("stop3".to_string(), Delay {
             arrival_delay: Some(1),
             departure_delay: None,
+            ..Default::default()
         });
 
         let mut delaymap = HashMap::new();
-        delaymap.insert("my-train".to_string(), trip_delays);
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
 
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
 
         check_delays(
             train,
@@ -271,17 +490,22 @@ mod tests {
         trip_delays.insert("stop1".to_string(), Delay {
             arrival_delay: None,
             departure_delay: Some(5),
+            ..Default::default()
         });
 
         trip_delays.insert("stop4".to_string(), Delay {
             arrival_delay: Some(3),
             departure_delay: None,
+            ..Default::default()
         });
 
         let mut delaymap = HashMap::new();
-        delaymap.insert("my-train".to_string(), trip_delays);
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
 
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
 
         check_delays(
             train,
@@ -299,22 +523,28 @@ mod tests {
         trip_delays.insert("stop1".to_string(), Delay {
             arrival_delay: None,
             departure_delay: Some(0),
+            ..Default::default()
         });
 
         trip_delays.insert("stop2".to_string(), Delay {
             arrival_delay: Some(-5),
             departure_delay: None,
+            ..Default::default()
         });
 
         trip_delays.insert("stop3".to_string(), Delay {
             arrival_delay: Some(0),
             departure_delay: None,
+            ..Default::default()
         });
 
         let mut delaymap = HashMap::new();
-        delaymap.insert("my-train".to_string(), trip_delays);
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
 
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
 
         check_delays(
             train,
@@ -324,6 +554,180 @@ mod tests {
         )
     }
 
+    // ----- LOOP TESTS -----
+    // The loop trip visits Stop 1 twice: Stop 1 -> Stop 2 -> Stop 1 -> Stop 4
+    #[test]
+    fn test_loop_sequence() {
+        let trip = create_loop_trip();
+        let mut trip_delay = TripDelay::default();
+
+        trip_delay.sequences.insert(3, Delay {
+            arrival_delay: Some(60),
+            departure_delay: Some(60),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), trip_delay);
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        check_delays(
+            train,
+            trip,
+            vec![None, Some(0), Some(60), Some(60)],
+            vec![Some(0), Some(0), Some(60), None]
+        )
+    }
+
+    #[test]
+    fn test_loop_stop_id_fallback() {
+        let trip = create_loop_trip();
+        let mut trip_delay = TripDelay::default();
+
+        trip_delay.sequences.insert(2, Delay {
+            arrival_delay: Some(30),
+            departure_delay: Some(30),
+            ..Default::default()
+        });
+        // Without a stop sequence, the update applies to both visits
+        trip_delay.stops.insert("stop1".to_string(), Delay {
+            arrival_delay: None,
+            departure_delay: Some(90),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), trip_delay);
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        check_delays(
+            train,
+            trip,
+            vec![None, Some(30), Some(30), Some(30)],
+            vec![Some(90), Some(30), Some(90), None]
+        )
+    }
+
+    // ----- VEHICLE POSITION TESTS -----
+    #[test]
+    fn test_vehicle_position() {
+        let mut trip = create_trip(40);
+        for (i, stop_time) in trip.stop_times.iter_mut().enumerate() {
+            stop_time.stop_sequence = i as u16 + 1;
+        }
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            vehicle: Some(Vehicle {
+                lat: Some(3.0),
+                lon: Some(4.5),
+                bearing: Some(90.0),
+                speed: Some(20.0),
+                current_stop_sequence: Some(3),
+                timestamp: Some(test_now().timestamp() as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        assert_eq!(train.position_source, PositionSource::Vehicle);
+        assert_eq!(train.estimated_lat, 3.0);
+        assert_eq!(train.estimated_lon, 4.5);
+        assert_eq!(train.bearing, Some(90.0));
+        assert_eq!(train.speed, Some(20.0));
+        assert_eq!(train.stop_index, 2);
+        assert!(!train.is_stopped);
+    }
+
+    #[test]
+    fn test_vehicle_position_outdated() {
+        let trip = create_trip(40);
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            vehicle: Some(Vehicle {
+                lat: Some(3.0),
+                lon: Some(4.5),
+                timestamp: Some((test_now() - Duration::hours(1)).timestamp() as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        assert_eq!(train.position_source, PositionSource::Interpolated);
+        assert_eq!(train.estimated_lat, 2.0);
+        assert_eq!(train.estimated_lon, 2.0);
+        assert_eq!(train.bearing, None);
+    }
+
+    // ----- OCCUPANCY TESTS -----
+    #[test]
+    fn test_occupancy() {
+        let trip = create_trip(40);
+        let mut trip_delays = HashMap::new();
+
+        trip_delays.insert("stop2".to_string(), Delay {
+            departure_occupancy: Some("standingRoomOnly".to_string()),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            vehicle: Some(Vehicle {
+                occupancy: Some(DelayMapOccupancy {
+                    status: Some("full".to_string()),
+                    percentage: None,
+                }),
+                carriages: vec![DelayMapCarriage {
+                    id: None,
+                    label: Some("Car 1".to_string()),
+                    sequence: Some(1),
+                    occupancy: None,
+                }],
+                timestamp: Some(test_now().timestamp() as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        assert_eq!(train.occupancy.unwrap().status, Some("full".to_string()));
+        assert_eq!(train.carriages.len(), 1);
+        assert_eq!(train.stops[0].departure_occupancy, None);
+        assert_eq!(train.stops[1].departure_occupancy, Some("standingRoomOnly".to_string()));
+        assert_eq!(train.stops[2].departure_occupancy, None);
+        // Without a position, the position is still interpolated
+        assert_eq!(train.position_source, PositionSource::Interpolated);
+    }
+
+    #[test]
+    fn test_occupancy_outdated() {
+        let trip = create_trip(40);
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            vehicle: Some(Vehicle {
+                occupancy: Some(DelayMapOccupancy {
+                    status: Some("full".to_string()),
+                    percentage: None,
+                }),
+                timestamp: Some((test_now() - Duration::hours(1)).timestamp() as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+        assert!(train.occupancy.is_none());
+    }
+
     // ----- INTERPOLATION + DELAY -----
     #[test]
     fn test_interpolation_delay() {
@@ -333,12 +737,16 @@ mod tests {
         trip_delays.insert("stop2".to_string(), Delay {
             arrival_delay: Some(60),
             departure_delay: Some(60),
+            ..Default::default()
         });
 
         let mut delaymap = HashMap::new();
-        delaymap.insert("my-train".to_string(), trip_delays);
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
 
-        let train = DelayMapTrain::from_gtfs(&trip, &delaymap);
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
 
         assert_eq!(train.estimated_lat, 2.0);
         assert_eq!(train.estimated_lon, 2.0);
@@ -346,7 +754,191 @@ mod tests {
         assert_eq!(train.is_stopped, false);
     }
 
+    // ----- SCHEDULE RELATIONSHIP TESTS -----
+    #[test]
+    fn test_cancelled() {
+        let trip = create_trip(40);
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            cancelled: true,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+        assert!(train.cancelled);
+
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), None, test_now());
+        assert!(!train.cancelled);
+    }
+
+    #[test]
+    fn test_skipped_stop() {
+        let trip = create_trip(80);
+        let mut trip_delays = HashMap::new();
+
+        trip_delays.insert("stop2".to_string(), Delay {
+            skipped: true,
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        // Riding from Stop 1 to Stop 3 without stopping at Stop 2
+        assert_eq!(train.estimated_lat, 2.0);
+        assert_eq!(train.estimated_lon, 4.0);
+        assert_eq!(train.stop_index, 2);
+        assert!(!train.is_stopped);
+        assert!(train.stops[1].skipped);
+        assert!(!train.stops[2].skipped);
+    }
+
+    // ----- ABSOLUTE TIME TESTS -----
+    #[test]
+    fn test_absolute_time() {
+        let trip = create_trip(0);
+        let day_start = service_day_start(NaiveDate::from_ymd(2022, 6, 19)).timestamp();
+        let scheduled_arrival = i64::from(trip.stop_times[1].arrival_time.unwrap());
+        let mut trip_delays = HashMap::new();
+
+        // Only the predicted time is given, 90 seconds after the scheduled time
+        trip_delays.insert("stop2".to_string(), Delay {
+            arrival_time: Some(day_start + scheduled_arrival + 90),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            stops: trip_delays,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+
+        check_delays(
+            train.clone(),
+            trip,
+            vec![None, Some(90), Some(90), Some(90)],
+            vec![Some(0), Some(0), Some(0), None]
+        );
+        assert_eq!(train.stops[1].predicted_arrival, Some(day_start + scheduled_arrival + 90));
+    }
+
+    #[test]
+    fn test_absolute_time_start_date() {
+        let trip = create_trip(0);
+        // Yesterday's trip, running a day late
+        let day_start = service_day_start(NaiveDate::from_ymd(2022, 6, 18)).timestamp();
+        let scheduled_arrival = i64::from(trip.stop_times[1].arrival_time.unwrap());
+        let mut trip_delays = HashMap::new();
+
+        trip_delays.insert("stop2".to_string(), Delay {
+            arrival_time: Some(day_start + scheduled_arrival + 24 * 60 * 60),
+            ..Default::default()
+        });
+
+        let mut delaymap = HashMap::new();
+        delaymap.insert("my-train".to_string(), TripDelay {
+            start_date: Some(NaiveDate::from_ymd(2022, 6, 18)),
+            stops: trip_delays,
+            ..Default::default()
+        });
+
+        let train = DelayMapTrain::from_gtfs(&trip, &delaymap, None, test_now());
+        assert_eq!(train.stops[1].arrival_delay, Some(24 * 60 * 60));
+    }
+
+    // ----- SHAPE TESTS -----
+    #[test]
+    fn test_shape_first_sector() {
+        // The shape goes east and then north to stop 2, instead of straight
+        let trip = create_trip(40);
+        let shape = create_shape(&[(0.0, 0.0), (0.0, 3.0), (3.0, 3.0), (3.0, 6.0), (6.0, 6.0)], false);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), Some(&shape), test_now());
+        assert!((train.estimated_lat - 1.0).abs() < 1e-6);
+        assert!((train.estimated_lon - 3.0).abs() < 1e-6);
+        assert!(!train.is_stopped);
+        assert_eq!(train.stop_index, 1);
+    }
+
+    #[test]
+    fn test_shape_in_station() {
+        let trip = create_trip(62);
+        let shape = create_shape(&[(0.0, 0.0), (0.0, 3.0), (3.0, 3.0), (3.0, 6.0), (6.0, 6.0)], false);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), Some(&shape), test_now());
+        assert_eq!(train.estimated_lat, 3.0);
+        assert_eq!(train.estimated_lon, 3.0);
+        assert!(train.is_stopped);
+    }
+
+    #[test]
+    fn test_shape_dist_traveled() {
+        // The distances in the feed are used instead of the stop coordinates,
+        // which are wrong for stop 3 here
+        let mut trip = create_trip(150);
+        for (i, stop_time) in trip.stop_times.iter_mut().enumerate() {
+            stop_time.shape_dist_traveled = Some(i as f32 * 10.0);
+        }
+        let mut stop = (*trip.stop_times[2].stop).clone();
+        stop.latitude = Some(0.0);
+        stop.longitude = Some(0.0);
+        trip.stop_times[2].stop = Arc::new(stop);
+        let shape = create_shape(&[(0.0, 0.0), (3.0, 3.0), (3.0, 6.0), (6.0, 6.0)], true);
+
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), Some(&shape), test_now());
+        assert!((train.estimated_lat - 4.2).abs() < 1e-6);
+        assert!((train.estimated_lon - 6.0).abs() < 1e-6);
+    }
+
+    // ----- WORKS TESTS -----
+    #[test]
+    fn test_works() {
+        let mut works_stop2 = DelayMapWorks::empty();
+        works_stop2.id = "works2".to_string();
+        works_stop2.impacted_station = Some(Stop { id: "stop2".to_string(), ..Stop::default() }.into());
+        let mut works_stop4 = DelayMapWorks::empty();
+        works_stop4.id = "works4".to_string();
+        works_stop4.end_station = Some(Stop { id: "stop4".to_string(), ..Stop::default() }.into());
+        let works = vec![works_stop2, works_stop4];
+
+        // Riding towards stop 2
+        let trip = create_trip(40);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), None, test_now())
+            .with_works(&trip, &works, test_now());
+        assert_eq!(train.affecting_works, vec!["works2".to_string(), "works4".to_string()]);
+
+        // Stop 2 has already been passed
+        let trip = create_trip(150);
+        let train = DelayMapTrain::from_gtfs(&trip, &HashMap::new(), None, test_now())
+            .with_works(&trip, &works, test_now());
+        assert_eq!(train.affecting_works, vec!["works4".to_string()]);
+    }
+
     // ----- HELPERS -----
+    // The distance traveled of each point is its index times 10
+    fn create_shape(points: &[(f64, f64)], dist_traveled: bool) -> ShapeLine {
+        let shape: Vec<Shape> = points
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| {
+                let mut point = Shape::default();
+                point.latitude = *lat;
+                point.longitude = *lon;
+                point.sequence = i;
+                if dist_traveled {
+                    point.dist_traveled = Some(i as f32 * 10.0);
+                }
+                point
+            })
+            .collect();
+        ShapeLine::new(&shape).unwrap()
+    }
+
     fn check_delays(
         train: DelayMapTrain,
         trip: Trip,
@@ -391,8 +983,25 @@ mod tests {
         return stoptime;
     }
 
+    fn hms(hour: u32, minute: u32) -> u32 {
+        hour * 60 * 60 + minute * 60
+    }
+
+    fn test_now() -> DateTime<Utc> {
+        Brussels.ymd(2022, 6, 19).and_hms(12, 0, 0).with_timezone(&Utc)
+    }
+
+    fn create_loop_trip() -> Trip {
+        let mut trip = create_trip(0);
+        trip.stop_times[2].stop = trip.stop_times[0].stop.clone();
+        for (i, stop_time) in trip.stop_times.iter_mut().enumerate() {
+            stop_time.stop_sequence = i as u16 + 1;
+        }
+        trip
+    }
+
     fn create_trip(delta: i32) -> Trip {
-        let local_datetime = Brussels.from_utc_datetime(&Utc::now().naive_utc());
+        let local_datetime = Brussels.from_utc_datetime(&test_now().naive_utc());
         let local_timestamp = local_datetime.time().num_seconds_from_midnight();
         let t_zero: u32 = (local_timestamp as i32 - delta).try_into().unwrap();
 
@@ -1,35 +1,127 @@
+use crate::delaymap_geometry::DelayMapGeometry;
 use crate::delaymap_stop::DelayMapStop;
+use crate::delaymap_train::DelayMapTrain;
+
+use gtfs_structures::Trip;
+
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Europe::Brussels;
 
 use serde::Serialize;
 
+// The times are in ISO 8601, with the offset of Brussels at that time. The
+// works are published from publication_start to publication_end, and the
+// incident itself lasts from start to end, between daily_start and daily_end
+// every day.
+//
+// The affected section goes from start_station to end_station. If a trip
+// passes both, the stops in between and the line along the track are known.
+//
+// The message is HTML with only basic formatting tags, message_text is the same
+// message as plain text. The name and the labels of the urls are plain text.
+//
+// The affected trips are the trains that will still pass one of the stations
+// of the works while they are active there.
 #[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DelayMapWorks {
     pub id: String,
-    pub name: String,
-    pub message: String,
+    pub name: Option<String>,
+    pub message: Option<String>,
+    pub message_text: Option<String>,
     pub impacted_station: Option<DelayMapStop>,
-    pub start_date: String,
-    pub end_date: String,
-    pub start_time: String,
-    pub end_time: String,
+    pub start_station: Option<DelayMapStop>,
+    pub end_station: Option<DelayMapStop>,
+    pub intermediate_stops: Vec<DelayMapStop>,
+    pub geometry: Option<DelayMapGeometry>,
+    pub start: Option<DateTime<FixedOffset>>,
+    pub end: Option<DateTime<FixedOffset>>,
+    pub daily_start: Option<NaiveTime>,
+    pub daily_end: Option<NaiveTime>,
+    pub publication_start: Option<DateTime<FixedOffset>>,
+    pub publication_end: Option<DateTime<FixedOffset>>,
     pub urls: Vec<DelayMapURL>,
+    pub affected_trips: Vec<String>,
 }
 
 impl DelayMapWorks {
     pub fn empty() -> Self {
         return DelayMapWorks {
             id: "Unknown id".to_string(),
-            name: "Unknown name".to_string(),
-            message: "No message given".to_string(),
+            name: None,
+            message: None,
+            message_text: None,
             impacted_station: None,
-            start_date: "Unknown start date".to_string(),
-            end_date: "Unknown end date".to_string(),
-            start_time: "Unknown start time".to_string(),
-            end_time: "Unknown end time".to_string(),
+            start_station: None,
+            end_station: None,
+            intermediate_stops: vec!(),
+            geometry: None,
+            start: None,
+            end: None,
+            daily_start: None,
+            daily_end: None,
+            publication_start: None,
+            publication_end: None,
             urls: vec!(),
+            affected_trips: vec!(),
+        }
+    }
+
+    // Whether the works are going on at the given moment. Missing bounds are
+    // open, and the daily window can go past midnight.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        if self.start.map(|start| now < start).unwrap_or(false)
+            || self.end.map(|end| now > end).unwrap_or(false)
+        {
+            return false;
+        }
+
+        match (self.daily_start, self.daily_end) {
+            (Some(daily_start), Some(daily_end)) => {
+                let time = Brussels.from_utc_datetime(&now.naive_utc()).time();
+                if daily_start <= daily_end {
+                    daily_start <= time && time <= daily_end
+                } else {
+                    daily_start <= time || time <= daily_end
+                }
+            }
+            _ => true,
         }
     }
+
+    // Whether the train still passes one of the stations of the works, while
+    // they are active at the moment it gets there
+    pub fn affects(&self, trip: &Trip, train: &DelayMapTrain, now: DateTime<Utc>) -> bool {
+        let stations: Vec<&str> = [&self.impacted_station, &self.start_station, &self.end_station]
+            .iter()
+            .filter_map(|station| station.as_ref())
+            .map(|station| station.stop_id.as_str())
+            .collect();
+
+        trip.stop_times
+            .iter()
+            .zip(&train.stops)
+            .skip(train.stop_index)
+            .filter(|(stop_time, _)| {
+                stations.iter().any(|&id| {
+                    stop_time.stop.id == id || stop_time.stop.parent_station.as_deref() == Some(id)
+                })
+            })
+            .any(|(_, stop)| {
+                // A train that is already at the station, or of which the time
+                // is unknown, is there now
+                let moment = stop.predicted_arrival
+                    .or(stop.predicted_departure)
+                    .and_then(|time| Utc.timestamp_opt(time, 0).single())
+                    .map(|moment| moment.max(now))
+                    .unwrap_or(now);
+                self.is_active(moment)
+            })
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -38,3 +130,149 @@ pub struct DelayMapURL {
     pub url: String,
     pub label: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gtfs_structures::Stop;
+    use gtfs_structures::StopTime;
+
+    use crate::delaymap_stop_time::DelayMapStopTime;
+
+    use chrono::Offset;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_active_bounds() {
+        let mut works = DelayMapWorks::empty();
+        assert!(works.is_active(utc(2022, 6, 19, 12)));
+
+        works.start = brussels(2022, 6, 18, 22);
+        works.end = brussels(2022, 6, 20, 5);
+        assert!(!works.is_active(utc(2022, 6, 18, 12)));
+        assert!(works.is_active(utc(2022, 6, 19, 12)));
+        assert!(!works.is_active(utc(2022, 6, 20, 12)));
+
+        // Only a start means the works go on until further notice
+        works.end = None;
+        assert!(works.is_active(utc(2022, 7, 1, 12)));
+    }
+
+    #[test]
+    fn test_active_daily() {
+        let mut works = DelayMapWorks::empty();
+        works.daily_start = Some(NaiveTime::from_hms(9, 0, 0));
+        works.daily_end = Some(NaiveTime::from_hms(17, 0, 0));
+        // 10:00 and 18:00 in Brussels
+        assert!(works.is_active(utc(2022, 6, 19, 8)));
+        assert!(!works.is_active(utc(2022, 6, 19, 16)));
+
+        // At night, from 22:00 to 05:00
+        works.daily_start = Some(NaiveTime::from_hms(22, 0, 0));
+        works.daily_end = Some(NaiveTime::from_hms(5, 0, 0));
+        assert!(works.is_active(utc(2022, 6, 19, 21)));
+        assert!(works.is_active(utc(2022, 6, 19, 1)));
+        assert!(!works.is_active(utc(2022, 6, 19, 12)));
+    }
+
+    #[test]
+    fn test_affects() {
+        let trip = create_trip(&["A", "B", "C"]);
+        let now = utc(2022, 6, 19, 12);
+        let mut works = DelayMapWorks::empty();
+        assert!(!works.affects(&trip, &create_train(&[None, None, None], 0), now));
+
+        // The works are on a station, the trip stops at one of its platforms
+        works.impacted_station = Some(create_station("B"));
+        assert!(works.affects(&trip, &create_train(&[None, None, None], 0), now));
+        assert!(works.affects(&trip, &create_train(&[None, None, None], 1), now));
+        // Stations that were already passed do not count
+        assert!(!works.affects(&trip, &create_train(&[None, None, None], 2), now));
+
+        works.impacted_station = None;
+        works.end_station = Some(create_station("C"));
+        assert!(works.affects(&trip, &create_train(&[None, None, None], 2), now));
+
+        // Works that are not going on do not affect the trip
+        works.end = brussels(2022, 6, 19, 10);
+        assert!(!works.affects(&trip, &create_train(&[None, None, None], 0), now));
+    }
+
+    #[test]
+    fn test_affects_when_passing() {
+        let trip = create_trip(&["A", "B", "C"]);
+        let mut works = DelayMapWorks::empty();
+        works.impacted_station = Some(create_station("B"));
+        // At night, from 22:00 to 05:00
+        works.daily_start = Some(NaiveTime::from_hms(22, 0, 0));
+        works.daily_end = Some(NaiveTime::from_hms(5, 0, 0));
+
+        // At 21:30, reaching B at 22:30
+        let now = utc(2022, 6, 19, 19) + chrono::Duration::minutes(30);
+        let train = create_train(&[None, brussels_timestamp(2022, 6, 19, 22, 30), None], 0);
+        assert!(works.affects(&trip, &train, now));
+
+        // At 04:30, only reaching B after the works at 05:30
+        let now = utc(2022, 6, 20, 2) + chrono::Duration::minutes(30);
+        let train = create_train(&[None, brussels_timestamp(2022, 6, 20, 5, 30), None], 0);
+        assert!(!works.affects(&trip, &train, now));
+
+        // Already at B since 21:50, while the works started at 22:00
+        let now = utc(2022, 6, 19, 20) + chrono::Duration::minutes(5);
+        let train = create_train(&[None, brussels_timestamp(2022, 6, 19, 21, 50), None], 1);
+        assert!(works.affects(&trip, &train, now));
+    }
+
+    // ----- HELPERS -----
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.ymd(year, month, day).and_hms(hour, 0, 0)
+    }
+
+    fn brussels(year: i32, month: u32, day: u32, hour: u32) -> Option<DateTime<FixedOffset>> {
+        let datetime = Brussels.ymd(year, month, day).and_hms(hour, 0, 0);
+        Some(datetime.with_timezone(&datetime.offset().fix()))
+    }
+
+    fn brussels_timestamp(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> Option<i64> {
+        Some(Brussels.ymd(year, month, day).and_hms(hour, minute, 0).timestamp())
+    }
+
+    // A train at the given stop, arriving at the stops at the given times
+    fn create_train(arrivals: &[Option<i64>], stop_index: usize) -> DelayMapTrain {
+        DelayMapTrain {
+            stops: arrivals
+                .iter()
+                .map(|&predicted_arrival| DelayMapStopTime {
+                    predicted_arrival,
+                    ..DelayMapStopTime::test_default()
+                })
+                .collect(),
+            stop_index,
+            ..DelayMapTrain::test_default()
+        }
+    }
+
+    fn create_station(id: &str) -> DelayMapStop {
+        let mut stop = Stop::default();
+        stop.id = id.to_string();
+        stop.into()
+    }
+
+    // Stops at the first platform of each given station
+    fn create_trip(stations: &[&str]) -> Trip {
+        let mut trip = Trip::default();
+        trip.id = "trip".to_string();
+        for (sequence, station) in stations.iter().enumerate() {
+            let mut platform = Stop::default();
+            platform.id = format!("{}_1", station);
+            platform.parent_station = Some(station.to_string());
+            let mut stop_time = StopTime::default();
+            stop_time.stop = Arc::new(platform);
+            stop_time.stop_sequence = sequence as u16 + 1;
+            trip.stop_times.push(stop_time);
+        }
+        trip
+    }
+}
@@ -1,14 +1,35 @@
+use crate::delaymap_geometry::DelayMapGeometry;
+use crate::delaymap_stop::DelayMapStop;
 use crate::delaymap_works::DelayMapWorks;
 use crate::delaymap_works::DelayMapURL;
-
+use crate::himmatch::HimParseError;
+use crate::himmatch::HimParser;
+use crate::himmatch::HimValue;
+use crate::html::decode_entities;
+use crate::html::sanitize;
+use crate::html::to_text;
+use crate::shape::trip_shape;
+use crate::shape::ShapeLine;
+
+use std::collections::HashMap;
 use std::sync::RwLockReadGuard;
 
 use gtfs_structures::Gtfs;
+use gtfs_structures::StopTime;
+use gtfs_structures::Trip;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::FixedOffset;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::Offset;
+use chrono::TimeZone;
+use chrono_tz::Europe::Brussels;
 
 pub struct DelayMapWorksParser {
     language: String,
-    lines: Vec<String>,
-    line_i: usize,
+    parser: HimParser,
 }
 
 impl DelayMapWorksParser {
@@ -18,109 +39,214 @@ impl DelayMapWorksParser {
     ) -> Self {
         Self {
             language: language.unwrap_or("en".to_string()),
-            lines: content.clone().lines().map(|x| x.to_string()).collect(),
-            line_i: 0
+            parser: HimParser::new(&content),
         }
     }
 
-    pub fn next_line(&mut self) -> Option<String> {
-        self.line_i += 1;
-        self.lines.get(self.line_i - 1).map(|x| x.to_string())
-    }
+    pub fn parse_next(
+        &mut self,
+        gtfs: RwLockReadGuard<Gtfs>,
+        shapes: &HashMap<String, ShapeLine>,
+        stations: &StationIndex,
+    ) -> Result<Option<DelayMapWorks>, HimParseError> {
+        let message = match self.parser.next_message()? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
 
-    pub fn parse_next(&mut self, gtfs: RwLockReadGuard<Gtfs>) -> Result<Option<DelayMapWorks>, ()> {
-        let mut maybe_line = self.next_line();
+        let mut ret = DelayMapWorks::empty();
 
-        if maybe_line == Some("himmessages=[".to_string()) {
-            maybe_line = self.next_line();
-            if maybe_line == Some("]".to_string()) {
-                // No messages
-                return Ok(None);
+        if let Some(value) = message.get_str("id") {
+            ret.id = value.to_string();
+        }
+        ret.name = message.get_str("caption").map(decode_entities);
+        ret.message = message.get_str("message").map(sanitize);
+        ret.message_text = message.get_str("message").map(to_text);
+        let datetime = |date: &str, time: &str| {
+            parse_datetime(message.get_str(date), message.get_str(time))
+        };
+        ret.start = datetime("startdate", "starttime");
+        ret.end = datetime("enddate", "endtime");
+        ret.daily_start = message.get_str("tstart").and_then(parse_time);
+        ret.daily_end = message.get_str("tend").and_then(parse_time);
+        ret.publication_start = datetime("pubstartdate_0", "pubstarttime_0");
+        ret.publication_end = datetime("pubenddate_0", "pubendtime_0");
+        let station = |key: &str| -> Option<DelayMapStop> {
+            let id = message.get_str(key)?;
+            gtfs.get_stop_translated(id, &self.language).ok().map(|stop| stop.into())
+        };
+        ret.impacted_station = station("impactstation_extId");
+        ret.start_station = station("startstation_extId");
+        ret.end_station = station("endstation_extId");
+        if let (Some(start), Some(end)) = (&ret.start_station, &ret.end_station) {
+            if let Some(section) = Section::find(&gtfs, stations, &start.stop_id, &end.stop_id) {
+                ret.intermediate_stops = section.intermediate_stops(&gtfs, &self.language);
+                ret.geometry = section.geometry(shapes);
             }
         }
+        if let Some(urls) = message.get("urllist").and_then(HimValue::as_array) {
+            ret.urls = urls.iter().map(parse_url).collect();
+        }
+
+        Ok(Some(ret))
+    }
+}
+
+// The trips stopping at every stop, and where in the trip they stop there.
+// Platforms are also listed under their station. Looking through all trips
+// for every works takes too long, so this is built once for every static feed.
+#[derive(Default)]
+pub struct StationIndex {
+    trips: HashMap<String, Vec<(String, usize)>>,
+}
 
-        if maybe_line != Some("{".to_string()) && maybe_line != Some(",{".to_string()) {
-            eprintln!("Invalid first line \"{:?}\"", maybe_line);
-            return Err(())
+impl StationIndex {
+    pub fn new(gtfs: &Gtfs) -> Self {
+        let mut trips: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+        for trip in gtfs.trips.values() {
+            for (i, stop_time) in trip.stop_times.iter().enumerate() {
+                let stop = &stop_time.stop;
+                for id in std::iter::once(&stop.id).chain(stop.parent_station.as_ref()) {
+                    let entries = trips.entry(id.clone()).or_default();
+                    // Only the first time the trip passes the station is used
+                    if entries.last().map(|(trip_id, _)| trip_id != &trip.id).unwrap_or(true) {
+                        entries.push((trip.id.clone(), i));
+                    }
+                }
+            }
         }
+        StationIndex { trips }
+    }
 
-        let mut ret = DelayMapWorks::empty();
+    fn get(&self, id: &str) -> &[(String, usize)] {
+        self.trips.get(id).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
 
-        while maybe_line != Some("}".to_string()) && maybe_line != None {
-            let line = maybe_line.unwrap();
-            let line_split = line.split_once(':');
-
-            if line_split.is_some() {
-                let (mut key, mut value) = line_split.unwrap();
-                key = key.strip_prefix(",").unwrap_or(key);
-                key = key.strip_prefix("\"").unwrap_or(key);
-                key = key.strip_suffix("\"").unwrap_or(key);
-
-                value = value.strip_prefix(",").unwrap_or(value);
-                value = value.strip_prefix("\"").unwrap_or(value);
-                value = value.strip_suffix("\"").unwrap_or(value);
-
-                match key {
-                    "id" => ret.id = value.to_string(),
-                    "caption" => ret.name = value.to_string(),
-                    "message" => ret.message = value.to_string(),
-                    "pubstartdate_0" => ret.start_date = value.to_string(),
-                    "pubstarttime_0" => ret.start_time = value.to_string(),
-                    "pubenddate_0" => ret.end_date = value.to_string(),
-                    "pubendtime_0" => ret.end_time = value.to_string(),
-                    "impactstation_extId" => ret.impacted_station =
-                            gtfs.get_stop_translated(value, &self.language)
-                                .map(|stop| stop.into())
-                                .ok(),
-                    "urllist" => {
-                        ret.urls = self.parse_urllist(line);
-                    },
-                    _ => {}
+// A part of a trip between two stations, in the order they were given
+struct Section<'a> {
+    trip: &'a Trip,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Section<'a> {
+    // Uses the trip that stops at most stations between the two, so the
+    // stations where only local trains stop are included as well
+    fn find(gtfs: &'a Gtfs, stations: &StationIndex, start_id: &str, end_id: &str) -> Option<Section<'a>> {
+        let ends: HashMap<&str, usize> = stations
+            .get(end_id)
+            .iter()
+            .map(|(trip_id, end)| (trip_id.as_str(), *end))
+            .collect();
+
+        let mut best: Option<Section> = None;
+        for (trip_id, start) in stations.get(start_id) {
+            if let (Some(trip), Some(&end)) = (gtfs.trips.get(trip_id), ends.get(trip_id.as_str())) {
+                if *start == end {
+                    continue;
+                }
+                let section = Section { trip, start: *start, end };
+                // The trip id makes the choice the same every time
+                let better = match &best {
+                    None => true,
+                    Some(best) => (section.len(), &best.trip.id) > (best.len(), &section.trip.id),
+                };
+                if better {
+                    best = Some(section);
                 }
             }
-            maybe_line = self.next_line();
         }
+        best
+    }
 
-        Ok(Some(ret))
+    fn len(&self) -> usize {
+        self.start.max(self.end) - self.start.min(self.end)
     }
 
-    fn parse_urllist(&mut self, line: String) -> Vec<DelayMapURL> {
-        let mut urls = vec!();
-        let mut urlline = line;
-        let mut curr_url = DelayMapURL {
-            label: "Link".to_string(),
-            url: "#".to_string(),
+    // The stop times from start to end
+    fn stop_times(&self) -> Vec<&'a StopTime> {
+        let mut stop_times: Vec<&StopTime> = self.trip.stop_times
+            [self.start.min(self.end)..=self.start.max(self.end)]
+            .iter()
+            .collect();
+        if self.start > self.end {
+            stop_times.reverse();
+        }
+        stop_times
+    }
+
+    // The stations between start and end, without start and end themselves
+    fn intermediate_stops(&self, gtfs: &Gtfs, language: &str) -> Vec<DelayMapStop> {
+        let stop_times = self.stop_times();
+        stop_times[1..stop_times.len() - 1]
+            .iter()
+            .filter_map(|stop_time| {
+                let id = stop_time.stop.parent_station.as_ref().unwrap_or(&stop_time.stop.id);
+                gtfs.get_stop_translated(id, language).ok()
+            })
+            .map(|stop| stop.into())
+            .collect()
+    }
+
+    // Follows the shape of the trip if it has one, otherwise straight lines
+    // between the stops
+    fn geometry(&self, shapes: &HashMap<String, ShapeLine>) -> Option<DelayMapGeometry> {
+        let location = |stop_time: &StopTime| {
+            Some((stop_time.stop.latitude?, stop_time.stop.longitude?))
         };
-        while urlline != "]" {
-            urlline = self.next_line().unwrap();
-            if urlline.ends_with("{") {
-                curr_url = DelayMapURL {
-                    label: "Link".to_string(),
-                    url: "#".to_string(),
-                };
-            } else if urlline.starts_with("}") {
-                urls.push(curr_url.clone());
-            } else {
-                let urlline_split = urlline.split_once(":");
-
-                if urlline_split.is_some() {
-                    let (mut urlkey, mut urlvalue) = urlline_split.unwrap();
-                    urlkey = urlkey.strip_prefix(",").unwrap_or(urlkey);
-                    urlkey = urlkey.strip_prefix("\"").unwrap_or(urlkey);
-                    urlkey = urlkey.strip_suffix("\"").unwrap_or(urlkey);
-
-                    urlvalue = urlvalue.strip_prefix(",").unwrap_or(urlvalue);
-                    urlvalue = urlvalue.strip_prefix("\"").unwrap_or(urlvalue);
-                    urlvalue = urlvalue.strip_suffix("\"").unwrap_or(urlvalue);
-                    match urlkey {
-                        "url" => curr_url.url = urlvalue.to_string(),
-                        "label" => curr_url.label = urlvalue.to_string(),
-                        _ => {}
-                    }
+        let stop_times = self.stop_times();
+        let (first, last) = if self.start < self.end {
+            (stop_times[0], stop_times[stop_times.len() - 1])
+        } else {
+            (stop_times[stop_times.len() - 1], stop_times[0])
+        };
+
+        // The section can only be found on the shape if both ends have a location
+        let points = match (trip_shape(shapes, self.trip), location(first), location(last)) {
+            (Some(shape), Some(first_location), Some(last_location)) => {
+                let from = shape.locate(first_location, first.shape_dist_traveled, 0.0);
+                let to = shape.locate(last_location, last.shape_dist_traveled, from);
+                let mut points = shape.slice(from, to);
+                if self.start > self.end {
+                    points.reverse();
                 }
+                points
             }
+            _ => stop_times.into_iter().filter_map(location).collect(),
+        };
+
+        if points.len() < 2 {
+            None
+        } else {
+            Some(DelayMapGeometry::line(&points))
         }
-        urls
+    }
+}
+
+// Times are given like 14:48
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+// Dates are given like 19.06.22, in the time zone of Brussels
+fn parse_datetime(date: Option<&str>, time: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    let date = NaiveDate::parse_from_str(date?.trim(), "%d.%m.%y").ok()?;
+    let time = parse_time(time?)?;
+    // When the clocks go back, the time can happen twice. When they go forward,
+    // the time does not exist and is moved past the skipped hour.
+    let local = date.and_time(time);
+    let datetime = Brussels
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| Brussels.from_local_datetime(&(local + Duration::hours(1))).earliest())?;
+    Some(datetime.with_timezone(&datetime.offset().fix()))
+}
+
+fn parse_url(url: &HimValue) -> DelayMapURL {
+    DelayMapURL {
+        url: url.get_str("url").unwrap_or("#").to_string(),
+        label: url.get_str("label").map(decode_entities).unwrap_or("Link".to_string()),
     }
 }
 
@@ -129,6 +255,9 @@ mod tests {
     use std::sync::RwLock;
     use std::sync::Arc;
 
+    use crate::shape::shape_lines;
+
+    use gtfs_structures::Shape;
     use gtfs_structures::Stop;
 
     use lazy_static::lazy_static;
@@ -156,6 +285,8 @@ mod tests {
         };
     }
 
+    use chrono::Utc;
+
     use super::*;
 
     #[test]
@@ -163,7 +294,7 @@ mod tests {
         let content = "himmessages=[
 ]".to_string();
         let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
-        let res = parser.parse_next(GTFS.read().unwrap());
+        let res = parse(&mut parser, &GTFS);
         assert!(res.is_ok());
         assert!(res.unwrap().is_none());
     }
@@ -235,38 +366,43 @@ mod tests {
 }".to_string();
         let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
 
-        let res = parser.parse_next(GTFS.read().unwrap());
+        let res = parse(&mut parser, &GTFS);
         assert!(res.is_ok());
         let res_unwrapped = res.unwrap();
         assert!(res_unwrapped.is_some());
         let first_message = res_unwrapped.unwrap();
 
         assert_eq!(first_message.id, "63381".to_string());
-        assert_eq!(first_message.name, "Landen - Sint-Truiden: Personen in de nabijheid van de sporen.".to_string());
-        assert_eq!(first_message.message, "De treinen rijden opnieuw normaal.<br />");
+        assert_eq!(first_message.name, Some("Landen - Sint-Truiden: Personen in de nabijheid van de sporen.".to_string()));
+        assert_eq!(first_message.message, Some("De treinen rijden opnieuw normaal.<br />".to_string()));
         assert!(first_message.impacted_station.is_some());
         assert_eq!(first_message.impacted_station.unwrap().name, "Sint-Truiden".to_string());
-        assert_eq!(first_message.start_date, "19.06.22".to_string());
-        assert_eq!(first_message.end_date, "19.06.22".to_string());
-        assert_eq!(first_message.start_time, "14:48".to_string());
-        assert_eq!(first_message.end_time, "23:59".to_string());
+        assert_eq!(first_message.start, brussels(14, 48));
+        assert_eq!(first_message.end, brussels(23, 59));
+        assert_eq!(first_message.daily_start, Some(NaiveTime::from_hms(0, 0, 0)));
+        assert_eq!(first_message.daily_end, Some(NaiveTime::from_hms(23, 59, 0)));
+        assert_eq!(first_message.publication_start, brussels(14, 48));
+        assert_eq!(first_message.publication_end, brussels(23, 59));
         assert!(first_message.urls.is_empty());
 
-        let res2 = parser.parse_next(GTFS.read().unwrap());
+        let res2 = parse(&mut parser, &GTFS);
         assert!(res2.is_ok());
         let res2_unwrapped = res2.unwrap();
         assert!(res2_unwrapped.is_some());
         let second_message = res2_unwrapped.unwrap();
 
         assert_eq!(second_message.id, "63382".to_string());
-        assert_eq!(second_message.name, "Bosvoorde - Groenendaal: Storing aan de seinen.".to_string());
-        assert_eq!(second_message.message, "Tussen Bosvoorde en Groenendaal:<br />Vertragingen zijn mogelijk.<br />Onbepaalde duur van de storing.<br />Luister naar de aankondigingen, raadpleeg de infoschermen of plan uw reis via de NMBS-app of nmbs.be voor meer info.");
+        assert_eq!(second_message.name, Some("Bosvoorde - Groenendaal: Storing aan de seinen.".to_string()));
+        assert_eq!(second_message.message, Some("Tussen Bosvoorde en Groenendaal:<br />Vertragingen zijn mogelijk.<br />Onbepaalde duur van de storing.<br />Luister naar de aankondigingen, raadpleeg de infoschermen of plan uw reis via de NMBS-app of nmbs.be voor meer info.".to_string()));
+        assert_eq!(second_message.message_text, Some("Tussen Bosvoorde en Groenendaal:\nVertragingen zijn mogelijk.\nOnbepaalde duur van de storing.\nLuister naar de aankondigingen, raadpleeg de infoschermen of plan uw reis via de NMBS-app of nmbs.be voor meer info.".to_string()));
         assert!(second_message.impacted_station.is_some());
         assert_eq!(second_message.impacted_station.unwrap().name, "Groenendaal".to_string());
-        assert_eq!(second_message.start_date, "19.06.22".to_string());
-        assert_eq!(second_message.end_date, "19.06.22".to_string());
-        assert_eq!(second_message.start_time, "16:00".to_string());
-        assert_eq!(second_message.end_time, "23:59".to_string());
+        assert_eq!(second_message.start, brussels(16, 0));
+        assert_eq!(second_message.end, brussels(23, 59));
+        assert_eq!(second_message.daily_start, Some(NaiveTime::from_hms(0, 0, 0)));
+        assert_eq!(second_message.daily_end, Some(NaiveTime::from_hms(23, 59, 0)));
+        assert_eq!(second_message.publication_start, brussels(16, 0));
+        assert_eq!(second_message.publication_end, brussels(23, 59));
         assert!(second_message.urls.is_empty());
     }
 
@@ -290,7 +426,7 @@ mod tests {
 ]".to_string();
         let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
 
-        let res = parser.parse_next(GTFS.read().unwrap());
+        let res = parse(&mut parser, &GTFS);
         assert!(res.is_ok());
         let res_unwrapped = res.unwrap();
         assert!(res_unwrapped.is_some());
@@ -308,4 +444,249 @@ mod tests {
         assert_eq!(first_url.label, "example");
         assert_eq!(second_url.label, "DelayMap");
     }
+
+    #[test]
+    fn test_dates() {
+        // 02:30 happens twice on this day, the first one is used
+        assert_eq!(
+            parse_datetime(Some("31.10.21"), Some("02:30")).map(|x| x.timestamp()),
+            Some(Utc.ymd(2021, 10, 31).and_hms(0, 30, 0).timestamp()),
+        );
+        // 02:30 does not exist on this day, it is 03:30 right after 01:59
+        assert_eq!(
+            parse_datetime(Some("27.03.22"), Some("02:30")).map(|x| x.timestamp()),
+            Some(Utc.ymd(2022, 3, 27).and_hms(1, 30, 0).timestamp()),
+        );
+        assert_eq!(parse_datetime(Some("19.06.22"), None), None);
+        assert_eq!(parse_datetime(Some("19/06/22"), Some("14:48")), None);
+        assert_eq!(parse_time("24:00"), None);
+
+        // The offset is included when serialized
+        let datetime = parse_datetime(Some("19.06.22"), Some("14:48"));
+        assert_eq!(serde_json::to_string(&datetime).unwrap(), "\"2022-06-19T14:48:00+02:00\"");
+    }
+
+    #[test]
+    fn test_no_dates() {
+        let content = "himmessages=[{\"id\":\"1\"}]".to_string();
+        let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
+        let message = parse(&mut parser, &GTFS).unwrap().unwrap();
+        assert_eq!(message.name, None);
+        assert_eq!(message.message, None);
+        assert_eq!(message.message_text, None);
+        assert_eq!(message.start, None);
+        assert_eq!(message.publication_end, None);
+        assert_eq!(message.daily_start, None);
+    }
+
+    #[test]
+    fn test_formatting() {
+        // Everything on one line, with a colon in the url and escaped quotes
+        let content = "himmessages=[{\"id\":\"1\",\"caption\":\"A \\\"B\\\"\",\"urllist\":[{\"url\":\"https://example.com/\"}],\"impactstation_extId\":\"8811445\"},{\"id\":\"2\"}]".to_string();
+        let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
+
+        let first_message = parse(&mut parser, &GTFS).unwrap().unwrap();
+        assert_eq!(first_message.id, "1".to_string());
+        assert_eq!(first_message.name, Some("A \"B\"".to_string()));
+        assert_eq!(first_message.urls.len(), 1);
+        assert_eq!(first_message.urls[0].url, "https://example.com/".to_string());
+        assert_eq!(first_message.urls[0].label, "Link".to_string());
+        assert_eq!(first_message.impacted_station.unwrap().name, "Groenendaal".to_string());
+
+        let second_message = parse(&mut parser, &GTFS).unwrap().unwrap();
+        assert_eq!(second_message.id, "2".to_string());
+        assert!(parse(&mut parser, &GTFS).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_html() {
+        let content = "himmessages=[{\"id\":\"1\",\"caption\":\"Li&egrave;ge &amp; Namur\",\"message\":\"<b>Travaux</b> &agrave; Li&egrave;ge<br /><script>alert(1)</script><a href=\\\"javascript:x\\\" onclick=\\\"x\\\">Info</a>\",\"urllist\":[{\"url\":\"https://example.com/\",\"label\":\"Plus d&#39;info\"}]}]".to_string();
+        let mut parser = DelayMapWorksParser::new(Some("fr".to_string()), content);
+
+        let message = parse(&mut parser, &GTFS).unwrap().unwrap();
+        assert_eq!(message.name, Some("Liège & Namur".to_string()));
+        assert_eq!(message.message, Some("<b>Travaux</b> à Liège<br /><a>Info</a>".to_string()));
+        assert_eq!(message.message_text, Some("Travaux à Liège\nInfo".to_string()));
+        assert_eq!(message.urls[0].label, "Plus d'info".to_string());
+    }
+
+    #[test]
+    fn test_missing_bracket() {
+        // The first message can be used, the second one is cut off
+        let content = "himmessages=[
+{
+\"id\":\"1\"
+}
+,{
+\"id\":\"2\"
+,\"urllist\":[
+".to_string();
+        let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
+
+        let first_message = parse(&mut parser, &GTFS).unwrap().unwrap();
+        assert_eq!(first_message.id, "1".to_string());
+
+        let error = parse(&mut parser, &GTFS).unwrap_err();
+        assert_eq!(error.line, 8);
+    }
+
+    #[test]
+    fn test_section() {
+        let gtfs = RwLock::new(create_line_gtfs());
+        let content = "himmessages=[{\"id\":\"1\",\"startstation_extId\":\"A\",\"endstation_extId\":\"D\",\"impactstation_extId\":\"B\"}]".to_string();
+        let mut parser = DelayMapWorksParser::new(Some("en".to_string()), content);
+        let message = parse(&mut parser, &gtfs).unwrap().unwrap();
+
+        assert_eq!(message.start_station.unwrap().name, "Station A".to_string());
+        assert_eq!(message.end_station.unwrap().name, "Station D".to_string());
+        assert_eq!(message.impacted_station.unwrap().name, "Station B".to_string());
+        // The local train is used, as the IC does not stop at B and C
+        let names: Vec<String> = message.intermediate_stops.into_iter().map(|x| x.name).collect();
+        assert_eq!(names, vec!["Station B".to_string(), "Station C".to_string()]);
+        assert_eq!(message.geometry, Some(DelayMapGeometry::line(&[
+            (0.0, 0.0), (0.0, 1.0), (0.0, 2.0), (0.0, 3.0),
+        ])));
+    }
+
+    #[test]
+    fn test_section_reversed() {
+        let gtfs = create_line_gtfs();
+        let section = Section::find(&gtfs, &StationIndex::new(&gtfs), "C", "A").unwrap();
+        let names: Vec<String> = section
+            .intermediate_stops(&gtfs, "en")
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        assert_eq!(names, vec!["Station B".to_string()]);
+        assert_eq!(section.geometry(&HashMap::new()), Some(DelayMapGeometry::line(&[
+            (0.0, 2.0), (0.0, 1.0), (0.0, 0.0),
+        ])));
+    }
+
+    #[test]
+    fn test_section_shape() {
+        let mut gtfs = create_line_gtfs();
+        add_local_shape(&mut gtfs);
+
+        let section = Section::find(&gtfs, &StationIndex::new(&gtfs), "B", "D").unwrap();
+        match section.geometry(&shape_lines(&gtfs)) {
+            Some(DelayMapGeometry::LineString { coordinates }) => {
+                assert_eq!(coordinates.len(), 4);
+                assert_eq!(coordinates[1], [1.5, 0.5]);
+            }
+            geometry => panic!("{:?} is not a line", geometry),
+        }
+    }
+
+    #[test]
+    fn test_section_shape_unknown_location() {
+        let mut gtfs = create_line_gtfs();
+        add_local_shape(&mut gtfs);
+        let local = gtfs.trips.get_mut("local").unwrap();
+        // The platform of D has no location
+        let mut platform = (*local.stop_times[3].stop).clone();
+        platform.latitude = None;
+        platform.longitude = None;
+        local.stop_times[3].stop = Arc::new(platform);
+
+        // The straight line through the stops that do have a location is used
+        let section = Section::find(&gtfs, &StationIndex::new(&gtfs), "B", "D").unwrap();
+        assert_eq!(section.geometry(&shape_lines(&gtfs)), Some(DelayMapGeometry::line(&[
+            (0.0, 1.0), (0.0, 2.0),
+        ])));
+    }
+
+    #[test]
+    fn test_no_section() {
+        let gtfs = create_line_gtfs();
+        assert!(Section::find(&gtfs, &StationIndex::new(&gtfs), "A", "A").is_none());
+        assert!(Section::find(&gtfs, &StationIndex::new(&gtfs), "A", "unknown").is_none());
+    }
+
+    #[test]
+    fn test_station_index() {
+        let mut gtfs = create_line_gtfs();
+        // A trip passing B twice
+        let mut trip = gtfs.trips["local"].clone();
+        trip.id = "loop".to_string();
+        trip.stop_times.push(trip.stop_times[1].clone());
+        gtfs.trips.insert(trip.id.clone(), trip);
+
+        let stations = StationIndex::new(&gtfs);
+        let mut trips = stations.get("B").to_vec();
+        trips.sort();
+        assert_eq!(trips, vec![("local".to_string(), 1), ("loop".to_string(), 1)]);
+        assert_eq!(stations.get("B_1").len(), 2);
+        assert_eq!(stations.get("D").len(), 3);
+        assert!(stations.get("unknown").is_empty());
+    }
+
+    // ----- HELPERS -----
+    fn parse(
+        parser: &mut DelayMapWorksParser,
+        gtfs: &RwLock<Gtfs>,
+    ) -> Result<Option<DelayMapWorks>, HimParseError> {
+        let stations = StationIndex::new(&gtfs.read().unwrap());
+        parser.parse_next(gtfs.read().unwrap(), &HashMap::new(), &stations)
+    }
+
+    // Stations A, B, C and D from west to east, with a local train stopping at
+    // the platforms of every station and an IC only stopping at A and D
+    fn create_line_gtfs() -> Gtfs {
+        let mut gtfs = Gtfs::default();
+        let mut platforms = vec![];
+        for (i, name) in ["A", "B", "C", "D"].iter().enumerate() {
+            let mut station = Stop::default();
+            station.id = name.to_string();
+            station.name = format!("Station {}", name);
+            station.latitude = Some(0.0);
+            station.longitude = Some(i as f64);
+            gtfs.stops.insert(station.id.clone(), Arc::new(station.clone()));
+
+            let mut platform = station;
+            platform.id = format!("{}_1", name);
+            platform.parent_station = Some(name.to_string());
+            let platform = Arc::new(platform);
+            gtfs.stops.insert(platform.id.clone(), platform.clone());
+            platforms.push(platform);
+        }
+
+        for (id, stops) in [("local", vec![0, 1, 2, 3]), ("ic", vec![0, 3])].iter() {
+            let mut trip = Trip::default();
+            trip.id = id.to_string();
+            for (sequence, &i) in stops.iter().enumerate() {
+                let mut stop_time = StopTime::default();
+                stop_time.stop = platforms[i].clone();
+                stop_time.stop_sequence = sequence as u16 + 1;
+                trip.stop_times.push(stop_time);
+            }
+            gtfs.trips.insert(trip.id.clone(), trip);
+        }
+        gtfs
+    }
+
+    // A shape for the local trip, where the track goes around a hill between
+    // B and C
+    fn add_local_shape(gtfs: &mut Gtfs) {
+        let points = [(0.0, 0.0), (0.0, 1.0), (0.5, 1.5), (0.0, 2.0), (0.0, 3.0)];
+        let shape = points
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| {
+                let mut point = Shape::default();
+                point.latitude = *lat;
+                point.longitude = *lon;
+                point.sequence = i;
+                point
+            })
+            .collect();
+        gtfs.shapes.insert("local-shape".to_string(), shape);
+        gtfs.trips.get_mut("local").unwrap().shape_id = Some("local-shape".to_string());
+    }
+
+    // The given time on 19/06/2022 in Brussels
+    fn brussels(hour: u32, minute: u32) -> Option<DateTime<FixedOffset>> {
+        let datetime = Brussels.ymd(2022, 6, 19).and_hms(hour, minute, 0);
+        Some(datetime.with_timezone(&datetime.offset().fix()))
+    }
 }
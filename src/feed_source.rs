@@ -0,0 +1,222 @@
+use gtfs_structures::Gtfs;
+
+use protobuf::ProtobufError;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+
+// A place to load a feed from. Remote feeds are downloaded over HTTP(S),
+// everything else is read from the local filesystem, either as a zip file or
+// as a directory of extracted GTFS text files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedSource {
+    Url(String),
+    Path(PathBuf),
+}
+
+impl FeedSource {
+    pub fn parse(source: &str) -> FeedSource {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            FeedSource::Url(source.to_string())
+        } else {
+            let path = source.strip_prefix("file://").unwrap_or(source);
+            FeedSource::Path(PathBuf::from(path))
+        }
+    }
+
+    pub fn load_gtfs(&self) -> Result<Gtfs, FeedError> {
+        let gtfs = match self {
+            FeedSource::Url(url) => Gtfs::from_url(url)?,
+            FeedSource::Path(path) => Gtfs::from_path(path)?,
+        };
+        validate_gtfs(&gtfs)?;
+        Ok(gtfs)
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Read>, FeedError> {
+        match self {
+            FeedSource::Url(url) => {
+                let response = reqwest::blocking::get(url)?.error_for_status()?;
+                Ok(Box::new(response))
+            }
+            FeedSource::Path(path) => Ok(Box::new(File::open(path)?)),
+        }
+    }
+}
+
+impl fmt::Display for FeedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedSource::Url(url) => write!(f, "{}", url),
+            FeedSource::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// Make sure a static feed can be used to replace the current one
+pub fn validate_gtfs(gtfs: &Gtfs) -> Result<(), FeedError> {
+    if gtfs.trips.is_empty() {
+        return Err(FeedError::Invalid("feed contains no trips"));
+    }
+    if gtfs.stops.is_empty() {
+        return Err(FeedError::Invalid("feed contains no stops"));
+    }
+    if gtfs.calendar.is_empty() && gtfs.calendar_dates.is_empty() {
+        return Err(FeedError::Invalid("feed contains no services"));
+    }
+    if gtfs.trips.values().any(|trip| trip.stop_times.is_empty()) {
+        return Err(FeedError::Invalid("feed contains trips without stop times"));
+    }
+    Ok(())
+}
+
+// Everything that can go wrong while fetching and decoding a feed
+#[derive(Debug)]
+pub enum FeedError {
+    Http(reqwest::Error),
+    Io(io::Error),
+    Decode(ProtobufError),
+    Gtfs(gtfs_structures::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::Http(e) => write!(f, "could not download feed: {}", e),
+            FeedError::Io(e) => write!(f, "could not read feed: {}", e),
+            FeedError::Decode(e) => write!(f, "could not decode feed: {}", e),
+            FeedError::Gtfs(e) => write!(f, "could not load GTFS: {}", e),
+            FeedError::Invalid(reason) => write!(f, "invalid feed: {}", reason),
+        }
+    }
+}
+
+impl Error for FeedError {}
+
+impl From<reqwest::Error> for FeedError {
+    fn from(e: reqwest::Error) -> Self {
+        FeedError::Http(e)
+    }
+}
+
+impl From<io::Error> for FeedError {
+    fn from(e: io::Error) -> Self {
+        FeedError::Io(e)
+    }
+}
+
+impl From<ProtobufError> for FeedError {
+    fn from(e: ProtobufError) -> Self {
+        FeedError::Decode(e)
+    }
+}
+
+impl From<gtfs_structures::Error> for FeedError {
+    fn from(e: gtfs_structures::Error) -> Self {
+        FeedError::Gtfs(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_structures::Calendar;
+    use gtfs_structures::Stop;
+    use gtfs_structures::StopTime;
+    use gtfs_structures::Trip;
+
+    use chrono::NaiveDate;
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            FeedSource::parse("https://example.com/gtfs.zip"),
+            FeedSource::Url("https://example.com/gtfs.zip".to_string())
+        );
+        assert_eq!(
+            FeedSource::parse("http://example.com/realtime"),
+            FeedSource::Url("http://example.com/realtime".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_file_url() {
+        assert_eq!(
+            FeedSource::parse("file:///data/gtfs.zip"),
+            FeedSource::Path(PathBuf::from("/data/gtfs.zip"))
+        );
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            FeedSource::parse("fixtures/gtfs/"),
+            FeedSource::Path(PathBuf::from("fixtures/gtfs/"))
+        );
+    }
+
+    #[test]
+    fn test_open_missing_file() {
+        let source = FeedSource::parse("file:///this/file/does/not/exist.pb");
+        assert!(matches!(source.open(), Err(FeedError::Io(_))));
+    }
+
+    #[test]
+    fn test_validate_empty() {
+        let gtfs = Gtfs::default();
+        assert!(matches!(validate_gtfs(&gtfs), Err(FeedError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let gtfs = create_gtfs();
+        assert!(validate_gtfs(&gtfs).is_ok());
+    }
+
+    #[test]
+    fn test_validate_no_stop_times() {
+        let mut gtfs = create_gtfs();
+        gtfs.trips.get_mut("trip1").unwrap().stop_times.clear();
+        assert!(matches!(validate_gtfs(&gtfs), Err(FeedError::Invalid(_))));
+    }
+
+    // ----- HELPERS -----
+    fn create_gtfs() -> Gtfs {
+        let mut stop = Stop::default();
+        stop.id = "stop1".to_string();
+        let stop = Arc::new(stop);
+
+        let mut stop_time = StopTime::default();
+        stop_time.stop = stop.clone();
+
+        let mut trip = Trip::default();
+        trip.id = "trip1".to_string();
+        trip.service_id = "service1".to_string();
+        trip.stop_times.push(stop_time);
+
+        let mut gtfs = Gtfs::default();
+        gtfs.stops.insert("stop1".to_string(), stop);
+        gtfs.trips.insert("trip1".to_string(), trip);
+        gtfs.calendar.insert("service1".to_string(), Calendar {
+            id: "service1".to_string(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date: NaiveDate::from_ymd(2022, 1, 1),
+            end_date: NaiveDate::from_ymd(2022, 12, 31),
+        });
+        gtfs
+    }
+}
@@ -0,0 +1,310 @@
+use crate::delaymap_stop::DelayMapStop;
+use crate::delaymap_train::DelayMapTrain;
+use crate::search::search_score;
+
+use gtfs_structures::Gtfs;
+use gtfs_structures::Trip;
+
+use rocket::FromForm;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    // Only a complete bounding box is used, partial ones are ignored
+    pub fn from_params(
+        min_lat: Option<f64>,
+        min_lon: Option<f64>,
+        max_lat: Option<f64>,
+        max_lon: Option<f64>,
+    ) -> Option<BoundingBox> {
+        Some(BoundingBox {
+            min_lat: min_lat?,
+            min_lon: min_lon?,
+            max_lat: max_lat?,
+            max_lon: max_lon?,
+        })
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+}
+
+#[derive(FromForm, Debug, Default)]
+pub struct StopFilter {
+    pub q: Option<String>,
+    pub min_lat: Option<f64>,
+    pub min_lon: Option<f64>,
+    pub max_lat: Option<f64>,
+    pub max_lon: Option<f64>,
+}
+
+impl StopFilter {
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::from_params(self.min_lat, self.min_lon, self.max_lat, self.max_lon)
+    }
+
+    // Keeps the matching stops, best search matches first, then by name
+    pub fn apply(&self, stops: Vec<DelayMapStop>) -> Vec<DelayMapStop> {
+        let bounding_box = self.bounding_box();
+
+        let mut scored_stops: Vec<(u32, DelayMapStop)> = stops
+            .into_iter()
+            .filter(|stop| match (&bounding_box, stop.lat, stop.lon) {
+                (None, _, _) => true,
+                (Some(bounding_box), Some(lat), Some(lon)) => bounding_box.contains(lat, lon),
+                (Some(_), _, _) => false,
+            })
+            .filter_map(|stop| match &self.q {
+                Some(q) => search_score(&stop.name, q).map(|score| (score, stop)),
+                None => Some((0, stop)),
+            })
+            .collect();
+
+        scored_stops.sort_by(|(score_a, stop_a), (score_b, stop_b)| {
+            score_a.cmp(score_b).then_with(|| stop_a.name.cmp(&stop_b.name))
+        });
+
+        scored_stops.into_iter().map(|(_, stop)| stop).collect()
+    }
+}
+
+#[derive(FromForm, Debug, Default)]
+pub struct TrainFilter {
+    pub min_lat: Option<f64>,
+    pub min_lon: Option<f64>,
+    pub max_lat: Option<f64>,
+    pub max_lon: Option<f64>,
+    pub route: Option<String>,
+    pub agency: Option<String>,
+    pub min_delay: Option<i32>,
+}
+
+impl TrainFilter {
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::from_params(self.min_lat, self.min_lon, self.max_lat, self.max_lon)
+    }
+
+    // Checks the filters that only need the static data, so trips can be
+    // skipped before their position is calculated
+    pub fn matches_trip(&self, gtfs: &Gtfs, trip: &Trip) -> bool {
+        if let Some(route) = &self.route {
+            if &trip.route_id != route {
+                return false;
+            }
+        }
+
+        if let Some(agency) = &self.agency {
+            let agency_id = gtfs.routes
+                .get(&trip.route_id)
+                .and_then(|route| route.agency_id.as_ref());
+            if agency_id != Some(agency) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn matches_train(&self, train: &DelayMapTrain) -> bool {
+        if let Some(bounding_box) = self.bounding_box() {
+            if !bounding_box.contains(train.estimated_lat, train.estimated_lon) {
+                return false;
+            }
+        }
+
+        if let Some(min_delay) = self.min_delay {
+            if train.current_delay() < min_delay {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delaymap_stop_time::DelayMapStopTime;
+
+    use gtfs_structures::Route;
+
+    use super::*;
+
+    #[test]
+    fn test_bounding_box_partial() {
+        assert_eq!(BoundingBox::from_params(Some(1.0), Some(2.0), Some(3.0), None), None);
+    }
+
+    #[test]
+    fn test_bounding_box_contains() {
+        let bounding_box = BoundingBox::from_params(Some(50.0), Some(3.0), Some(51.0), Some(4.0))
+            .unwrap();
+        assert!(bounding_box.contains(50.5, 3.5));
+        assert!(bounding_box.contains(50.0, 4.0));
+        assert!(!bounding_box.contains(51.5, 3.5));
+        assert!(!bounding_box.contains(50.5, 4.5));
+    }
+
+    #[test]
+    fn test_no_filter() {
+        let stops = StopFilter::default().apply(create_stops());
+        let names: Vec<&str> = stops.iter().map(|stop| stop.name.as_str()).collect();
+        assert_eq!(names, vec!["Brugge", "Brussel-Zuid", "Gent-Sint-Pieters", "Nowhere"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let filter = StopFilter {
+            q: Some("bru".to_string()),
+            ..Default::default()
+        };
+        let stops = filter.apply(create_stops());
+        let names: Vec<&str> = stops.iter().map(|stop| stop.name.as_str()).collect();
+        assert_eq!(names, vec!["Brugge", "Brussel-Zuid"]);
+    }
+
+    #[test]
+    fn test_search_order() {
+        let filter = StopFilter {
+            q: Some("zuid".to_string()),
+            ..Default::default()
+        };
+        let stops = filter.apply(vec![
+            create_stop("Brussel-Zuid", Some(50.8357), Some(4.3365)),
+            create_stop("Zuidstation", Some(0.0), Some(0.0)),
+        ]);
+        let names: Vec<&str> = stops.iter().map(|stop| stop.name.as_str()).collect();
+        assert_eq!(names, vec!["Zuidstation", "Brussel-Zuid"]);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        // Roughly the province of West Flanders
+        let filter = StopFilter {
+            min_lat: Some(50.7),
+            min_lon: Some(2.5),
+            max_lat: Some(51.4),
+            max_lon: Some(3.5),
+            ..Default::default()
+        };
+        let stops = filter.apply(create_stops());
+        let names: Vec<&str> = stops.iter().map(|stop| stop.name.as_str()).collect();
+        assert_eq!(names, vec!["Brugge"]);
+    }
+
+    #[test]
+    fn test_train_no_filter() {
+        let gtfs = create_gtfs();
+        let filter = TrainFilter::default();
+        assert!(filter.matches_trip(&gtfs, &create_trip("ic-route")));
+        assert!(filter.matches_train(&create_train(51.0, 3.7, 0)));
+    }
+
+    #[test]
+    fn test_train_route() {
+        let gtfs = create_gtfs();
+        let filter = TrainFilter {
+            route: Some("ic-route".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches_trip(&gtfs, &create_trip("ic-route")));
+        assert!(!filter.matches_trip(&gtfs, &create_trip("l-route")));
+    }
+
+    #[test]
+    fn test_train_agency() {
+        let gtfs = create_gtfs();
+        let filter = TrainFilter {
+            agency: Some("nmbs".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches_trip(&gtfs, &create_trip("ic-route")));
+        assert!(!filter.matches_trip(&gtfs, &create_trip("l-route")));
+        assert!(!filter.matches_trip(&gtfs, &create_trip("unknown-route")));
+    }
+
+    #[test]
+    fn test_train_bounding_box() {
+        let filter = TrainFilter {
+            min_lat: Some(50.7),
+            min_lon: Some(2.5),
+            max_lat: Some(51.4),
+            max_lon: Some(3.5),
+            ..Default::default()
+        };
+        assert!(filter.matches_train(&create_train(51.1972, 3.2167, 0)));
+        assert!(!filter.matches_train(&create_train(50.8357, 4.3365, 0)));
+    }
+
+    #[test]
+    fn test_train_min_delay() {
+        let filter = TrainFilter {
+            min_delay: Some(300),
+            ..Default::default()
+        };
+        assert!(filter.matches_train(&create_train(51.0, 3.7, 300)));
+        assert!(!filter.matches_train(&create_train(51.0, 3.7, 60)));
+    }
+
+    // ----- HELPERS -----
+    fn create_gtfs() -> Gtfs {
+        let mut gtfs = Gtfs::default();
+        for (route_id, agency_id) in &[("ic-route", "nmbs"), ("l-route", "other")] {
+            let mut route = Route::default();
+            route.id = route_id.to_string();
+            route.agency_id = Some(agency_id.to_string());
+            gtfs.routes.insert(route_id.to_string(), route);
+        }
+        gtfs
+    }
+
+    fn create_trip(route_id: &str) -> Trip {
+        let mut trip = Trip::default();
+        trip.route_id = route_id.to_string();
+        trip
+    }
+
+    fn create_train(lat: f64, lon: f64, delay: i32) -> DelayMapTrain {
+        DelayMapTrain {
+            stops: vec![DelayMapStopTime {
+                name: "Stop 1".to_string(),
+                lat: Some(lat),
+                lon: Some(lon),
+                departure_delay: Some(delay),
+                departure_timestamp: Some(0),
+                predicted_departure: Some(0),
+                stop_id: "stop1".to_string(),
+                ..DelayMapStopTime::test_default()
+            }],
+            is_stopped: true,
+            estimated_lat: lat,
+            estimated_lon: lon,
+            ..DelayMapTrain::test_default()
+        }
+    }
+
+    fn create_stop(name: &str, lat: Option<f64>, lon: Option<f64>) -> DelayMapStop {
+        DelayMapStop {
+            name: name.to_string(),
+            lat,
+            lon,
+            stop_id: name.to_lowercase(),
+        }
+    }
+
+    fn create_stops() -> Vec<DelayMapStop> {
+        vec![
+            create_stop("Gent-Sint-Pieters", Some(51.0359), Some(3.7108)),
+            create_stop("Brussel-Zuid", Some(50.8357), Some(4.3365)),
+            create_stop("Nowhere", None, None),
+            create_stop("Brugge", Some(51.1972), Some(3.2167)),
+        ]
+    }
+}
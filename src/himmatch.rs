@@ -0,0 +1,501 @@
+use std::fmt;
+
+// A value in the JavaScript-like output of the HAFAS himmatch template. Numbers
+// and other bare words are kept as strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HimValue {
+    String(String),
+    Array(Vec<HimValue>),
+    // The keys in the order they were given
+    Object(Vec<(String, HimValue)>),
+}
+
+impl HimValue {
+    pub fn get(&self, key: &str) -> Option<&HimValue> {
+        match self {
+            HimValue::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            HimValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[HimValue]> {
+        match self {
+            HimValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    // The string value of the key, if there is one
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key)?.as_str()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HimParseErrorKind {
+    UnterminatedString,
+    // A \u escape without four hex digits, or with an unpaired surrogate
+    InvalidEscape,
+    UnexpectedCharacter(char),
+    UnexpectedToken { expected: &'static str, found: String },
+    UnexpectedEnd { expected: &'static str },
+}
+
+// Where parsing failed, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct HimParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: HimParseErrorKind,
+}
+
+impl fmt::Display for HimParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            HimParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            HimParseErrorKind::InvalidEscape => write!(f, "invalid unicode escape"),
+            HimParseErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            HimParseErrorKind::UnexpectedToken { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            HimParseErrorKind::UnexpectedEnd { expected } => {
+                write!(f, "expected {}, found the end of the input", expected)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    String(String),
+    // Bare words like himmessages, numbers, true or null
+    Word(String),
+    Punctuation(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::String(value) => write!(f, "string {:?}", value),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Punctuation(c) => write!(f, "{:?}", c),
+        }
+    }
+}
+
+struct Tokenizer {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Tokenizer {
+    fn new(content: &str) -> Self {
+        Tokenizer {
+            chars: content.chars().collect(),
+            index: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, kind: HimParseErrorKind) -> HimParseError {
+        HimParseError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    // The next token and the position it starts at, None at the end
+    fn next_token(&mut self) -> Result<Option<(Token, usize, usize)>, HimParseError> {
+        while self.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.bump();
+        }
+        let (line, column) = (self.line, self.column);
+
+        let token = match self.peek() {
+            None => return Ok(None),
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.bump();
+                Token::String(self.string(quote, line, column)?)
+            }
+            Some(c) if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' => {
+                let mut word = String::new();
+                while let Some(c) = self.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '-' || c == '.') {
+                        break;
+                    }
+                    word.push(c);
+                    self.bump();
+                }
+                Token::Word(word)
+            }
+            Some(c) if "[]{},:=;".contains(c) => {
+                self.bump();
+                Token::Punctuation(c)
+            }
+            Some(c) => return Err(self.error(HimParseErrorKind::UnexpectedCharacter(c))),
+        };
+        Ok(Some((token, line, column)))
+    }
+
+    // The rest of a string after its opening quote
+    fn string(&mut self, quote: char, line: usize, column: usize) -> Result<String, HimParseError> {
+        let unterminated = HimParseError {
+            line,
+            column,
+            kind: HimParseErrorKind::UnterminatedString,
+        };
+
+        let mut value = String::new();
+        loop {
+            let (escape_line, escape_column) = (self.line, self.column);
+            match self.bump().ok_or_else(|| unterminated.clone())? {
+                c if c == quote => return Ok(value),
+                '\\' => match self.bump().ok_or_else(|| unterminated.clone())? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    'u' => value.push(self.unicode_escape(escape_line, escape_column)?),
+                    // Quotes, slashes and anything else are taken literally
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    // The character of a \u escape, after the \u. Characters outside of the
+    // basic plane are written as two escapes, a high and a low surrogate.
+    fn unicode_escape(&mut self, line: usize, column: usize) -> Result<char, HimParseError> {
+        let invalid = HimParseError {
+            line,
+            column,
+            kind: HimParseErrorKind::InvalidEscape,
+        };
+
+        let mut code = self.hex_digits(&invalid)?;
+        if (0xD800..=0xDBFF).contains(&code) {
+            if self.peek() != Some('\\') || self.chars.get(self.index + 1) != Some(&'u') {
+                return Err(invalid);
+            }
+            self.bump();
+            self.bump();
+            let low = self.hex_digits(&invalid)?;
+            if !(0xDC00..=0xDFFF).contains(&low) {
+                return Err(invalid);
+            }
+            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+        }
+        // A low surrogate on its own is not a character
+        std::char::from_u32(code).ok_or(invalid)
+    }
+
+    // Exactly four hex digits, anything else is left for the string
+    fn hex_digits(&mut self, invalid: &HimParseError) -> Result<u32, HimParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.peek()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| invalid.clone())?;
+            self.bump();
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+// Reads the himmessages=[...] list one message at a time, so the messages
+// before an error can still be used. The assignment and the brackets around
+// the list are optional.
+pub struct HimParser {
+    tokenizer: Tokenizer,
+    peeked: Option<Option<(Token, usize, usize)>>,
+    started: bool,
+}
+
+impl HimParser {
+    pub fn new(content: &str) -> Self {
+        HimParser {
+            tokenizer: Tokenizer::new(content),
+            peeked: None,
+            started: false,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>, HimParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.tokenizer.next_token()?);
+        }
+        Ok(self.peeked.as_ref().unwrap().as_ref().map(|(token, _, _)| token))
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, usize, usize)>, HimParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.tokenizer.next_token(),
+        }
+    }
+
+    // Takes the next token if it is the given punctuation
+    fn eat(&mut self, c: char) -> Result<bool, HimParseError> {
+        if self.peek()? == Some(&Token::Punctuation(c)) {
+            self.next()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn unexpected(&mut self, expected: &'static str) -> HimParseError {
+        match self.next() {
+            Ok(Some((token, line, column))) => HimParseError {
+                line,
+                column,
+                kind: HimParseErrorKind::UnexpectedToken {
+                    expected,
+                    found: token.to_string(),
+                },
+            },
+            Ok(None) => self.tokenizer.error(HimParseErrorKind::UnexpectedEnd { expected }),
+            Err(e) => e,
+        }
+    }
+
+    // The next message in the list, or None if there are no more
+    pub fn next_message(&mut self) -> Result<Option<HimValue>, HimParseError> {
+        if !self.started {
+            self.started = true;
+            if let Some(Token::Word(_)) = self.peek()? {
+                self.next()?;
+                if !self.eat('=')? {
+                    return Err(self.unexpected("'='"));
+                }
+            }
+            self.eat('[')?;
+        }
+
+        // Separators and stray commas between messages are skipped
+        while self.eat(',')? {}
+
+        match self.peek()? {
+            // The end of the list, a missing ] is tolerated
+            None | Some(Token::Punctuation(']')) | Some(Token::Punctuation(';')) => Ok(None),
+            Some(Token::Punctuation('{')) => self.value().map(Some),
+            _ => Err(self.unexpected("a message")),
+        }
+    }
+
+    fn value(&mut self) -> Result<HimValue, HimParseError> {
+        match self.next()? {
+            Some((Token::String(value), _, _)) | Some((Token::Word(value), _, _)) => {
+                Ok(HimValue::String(value))
+            }
+            Some((Token::Punctuation('{'), _, _)) => self.object(),
+            Some((Token::Punctuation('['), _, _)) => self.array(),
+            Some((token, line, column)) => Err(HimParseError {
+                line,
+                column,
+                kind: HimParseErrorKind::UnexpectedToken {
+                    expected: "a value",
+                    found: token.to_string(),
+                },
+            }),
+            None => Err(self.tokenizer.error(HimParseErrorKind::UnexpectedEnd {
+                expected: "a value",
+            })),
+        }
+    }
+
+    // The rest of an object after its {
+    fn object(&mut self) -> Result<HimValue, HimParseError> {
+        let mut fields = vec![];
+        loop {
+            while self.eat(',')? {}
+            if self.eat('}')? {
+                return Ok(HimValue::Object(fields));
+            }
+
+            let key = match self.next()? {
+                Some((Token::String(key), _, _)) | Some((Token::Word(key), _, _)) => key,
+                Some((token, line, column)) => {
+                    return Err(HimParseError {
+                        line,
+                        column,
+                        kind: HimParseErrorKind::UnexpectedToken {
+                            expected: "a key or '}'",
+                            found: token.to_string(),
+                        },
+                    })
+                }
+                None => {
+                    return Err(self.tokenizer.error(HimParseErrorKind::UnexpectedEnd {
+                        expected: "a key or '}'",
+                    }))
+                }
+            };
+            if !self.eat(':')? {
+                return Err(self.unexpected("':'"));
+            }
+            fields.push((key, self.value()?));
+        }
+    }
+
+    // The rest of an array after its [
+    fn array(&mut self) -> Result<HimValue, HimParseError> {
+        let mut values = vec![];
+        loop {
+            while self.eat(',')? {}
+            if self.eat(']')? {
+                return Ok(HimValue::Array(values));
+            }
+            if self.peek()?.is_none() {
+                return Err(self.unexpected("a value or ']'"));
+            }
+            values.push(self.value()?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(HimParser::new("").next_message(), Ok(None));
+        assert_eq!(HimParser::new("himmessages=[\n]").next_message(), Ok(None));
+        assert_eq!(HimParser::new("himmessages = [ ] ;").next_message(), Ok(None));
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut parser = HimParser::new("himmessages=[{\"id\":\"1\"},{\"id\":\"2\"}]");
+        assert_eq!(parser.next_message().unwrap().unwrap().get_str("id"), Some("1"));
+        assert_eq!(parser.next_message().unwrap().unwrap().get_str("id"), Some("2"));
+        assert_eq!(parser.next_message(), Ok(None));
+        assert_eq!(parser.next_message(), Ok(None));
+    }
+
+    #[test]
+    fn test_without_list() {
+        // Only the messages, without the assignment and the brackets
+        let mut parser = HimParser::new("{\n\"id\":\"1\"\n}\n,{\n\"id\":\"2\"\n}");
+        assert_eq!(parser.next_message().unwrap().unwrap().get_str("id"), Some("1"));
+        assert_eq!(parser.next_message().unwrap().unwrap().get_str("id"), Some("2"));
+        assert_eq!(parser.next_message(), Ok(None));
+    }
+
+    #[test]
+    fn test_values() {
+        let mut parser = HimParser::new(
+            "[{ \"url\" : \"https://example.com/a:b\", 'quote': \"say \\\"hi\\\"\",
+                priority: 25, \"unicode\": \"caf\\u00e9\", \"list\": [\"a\", , \"b\",], }]",
+        );
+        let message = parser.next_message().unwrap().unwrap();
+        assert_eq!(message.get_str("url"), Some("https://example.com/a:b"));
+        assert_eq!(message.get_str("quote"), Some("say \"hi\""));
+        assert_eq!(message.get_str("priority"), Some("25"));
+        assert_eq!(message.get_str("unicode"), Some("café"));
+        assert_eq!(
+            message.get("list").and_then(|list| list.as_array()),
+            Some(&[HimValue::String("a".to_string()), HimValue::String("b".to_string())][..]),
+        );
+        assert_eq!(message.get_str("missing"), None);
+        assert_eq!(message.get_str("list"), None);
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let mut parser = HimParser::new("[{\n\"id\":\"1}]");
+        assert_eq!(parser.next_message(), Err(HimParseError {
+            line: 2,
+            column: 6,
+            kind: HimParseErrorKind::UnterminatedString,
+        }));
+    }
+
+    #[test]
+    fn test_invalid_escape() {
+        // The closing quote is not part of the escape
+        let mut parser = HimParser::new("[{\"id\":\"\\u12\", \"name\": \"x\"}]");
+        assert_eq!(parser.next_message(), Err(HimParseError {
+            line: 1,
+            column: 9,
+            kind: HimParseErrorKind::InvalidEscape,
+        }));
+
+        // Surrogates without their other half
+        for content in &["[{\"id\":\"\\ud83d\"}]", "[{\"id\":\"\\ud83dx\"}]", "[{\"id\":\"\\ude80\"}]"] {
+            let error = HimParser::new(content).next_message().unwrap_err();
+            assert_eq!(error.kind, HimParseErrorKind::InvalidEscape);
+            assert_eq!(error.to_string(), "line 1, column 9: invalid unicode escape");
+        }
+    }
+
+    #[test]
+    fn test_surrogate_pair() {
+        let mut parser = HimParser::new("[{\"name\":\"\\ud83d\\ude80 train\"}]");
+        let message = parser.next_message().unwrap().unwrap();
+        assert_eq!(message.get_str("name"), Some("\u{1F680} train"));
+    }
+
+    #[test]
+    fn test_missing_bracket() {
+        let mut parser = HimParser::new("[{\"urllist\":[{\"url\":\"a\"}");
+        let error = parser.next_message().unwrap_err();
+        assert_eq!(error.kind, HimParseErrorKind::UnexpectedEnd { expected: "a value or ']'" });
+        assert_eq!(error.to_string(), "line 1, column 25: expected a value or ']', found the end of the input");
+    }
+
+    #[test]
+    fn test_unexpected_token() {
+        let mut parser = HimParser::new("[{\"id\" \"1\"}]");
+        assert_eq!(parser.next_message(), Err(HimParseError {
+            line: 1,
+            column: 8,
+            kind: HimParseErrorKind::UnexpectedToken {
+                expected: "':'",
+                found: "string \"1\"".to_string(),
+            },
+        }));
+    }
+
+    #[test]
+    fn test_unexpected_character() {
+        let mut parser = HimParser::new("[{\"id\": @}]");
+        assert_eq!(
+            parser.next_message().unwrap_err().kind,
+            HimParseErrorKind::UnexpectedCharacter('@'),
+        );
+    }
+}
@@ -0,0 +1,470 @@
+// Only these tags are kept when sanitizing, without their attributes except
+// for the href of links
+const ALLOWED_TAGS: &[&str] = &["a", "b", "br", "em", "i", "li", "ol", "p", "strong", "u", "ul"];
+// Tags without content or closing tag
+const VOID_TAGS: &[&str] = &["br", "hr", "img", "input", "meta", "link", "wbr"];
+// Tags whose content is not text either
+const DROPPED_TAGS: &[&str] = &["script", "style", "iframe", "object", "template"];
+// Tags that start a new line in the plain text, list items start with a dash
+const BLOCK_TAGS: &[&str] = &["div", "h1", "h2", "h3", "h4", "h5", "h6", "ol", "p", "tr", "ul"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // Still containing the entities
+    Text(String),
+    Tag(Tag),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Tag {
+    // In lowercase
+    name: String,
+    closing: bool,
+    // With the entities decoded
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Splits HTML into text and tags. Comments and doctypes are left out, a tag
+// that is not closed is dropped with the rest of the input.
+struct Tokenizer {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Tokenizer {
+    fn new(html: &str) -> Self {
+        Tokenizer {
+            chars: html.chars().collect(),
+            index: 0,
+        }
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    fn starts_with(&self, value: &str) -> bool {
+        value.chars().enumerate().all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    // Whether a tag or comment starts here, instead of a lone <
+    fn at_markup(&self) -> bool {
+        match (self.peek_at(0), self.peek_at(1), self.peek_at(2)) {
+            (Some('<'), Some('!'), _) => true,
+            (Some('<'), Some('/'), Some(c)) => c.is_ascii_alphabetic(),
+            (Some('<'), Some(c), _) => c.is_ascii_alphabetic(),
+            _ => false,
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) {
+        while self.index < self.chars.len() && !self.starts_with(end) {
+            self.index += 1;
+        }
+        self.index = (self.index + end.len()).min(self.chars.len());
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek_at(0).map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.index += 1;
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek_at(0) {
+            if c.is_whitespace() || "/>=\"'".contains(c) {
+                break;
+            }
+            word.push(c);
+            self.index += 1;
+        }
+        word
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        loop {
+            if self.index >= self.chars.len() {
+                return None;
+            }
+
+            if !self.at_markup() {
+                let mut text = String::new();
+                text.push(self.chars[self.index]);
+                self.index += 1;
+                while self.index < self.chars.len() && !self.at_markup() {
+                    text.push(self.chars[self.index]);
+                    self.index += 1;
+                }
+                return Some(Token::Text(text));
+            }
+
+            if self.starts_with("<!--") {
+                self.skip_past("-->");
+            } else if self.starts_with("<!") {
+                self.skip_past(">");
+            } else {
+                return self.tag();
+            }
+        }
+    }
+
+    // The tag starting at the current <
+    fn tag(&mut self) -> Option<Token> {
+        self.index += 1;
+        let closing = self.peek_at(0) == Some('/');
+        if closing {
+            self.index += 1;
+        }
+        let name = self.word().to_lowercase();
+
+        let mut attributes = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.peek_at(0)? {
+                '>' => {
+                    self.index += 1;
+                    break;
+                }
+                '/' | '"' | '\'' | '=' => self.index += 1,
+                _ => {
+                    let attribute = self.word().to_lowercase();
+                    self.skip_whitespace();
+                    let mut value = String::new();
+                    if self.peek_at(0) == Some('=') {
+                        self.index += 1;
+                        self.skip_whitespace();
+                        value = self.attribute_value()?;
+                    }
+                    attributes.push((attribute, decode_entities(&value)));
+                }
+            }
+        }
+
+        Some(Token::Tag(Tag {
+            name,
+            closing,
+            attributes,
+        }))
+    }
+
+    fn attribute_value(&mut self) -> Option<String> {
+        match self.peek_at(0)? {
+            quote if quote == '"' || quote == '\'' => {
+                self.index += 1;
+                let mut value = String::new();
+                loop {
+                    let c = self.peek_at(0)?;
+                    self.index += 1;
+                    if c == quote {
+                        return Some(value);
+                    }
+                    value.push(c);
+                }
+            }
+            _ => Some(self.word()),
+        }
+    }
+}
+
+impl Iterator for Tokenizer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_token()
+    }
+}
+
+// Only keeps the allowed tags and closes the ones that were left open, so the
+// result can be put in a web page as is
+pub fn sanitize(html: &str) -> String {
+    let mut ret = String::new();
+    let mut open_tags: Vec<String> = vec![];
+    let mut dropping: Option<String> = None;
+
+    for token in Tokenizer::new(html) {
+        let tag = match token {
+            Token::Text(text) => {
+                if dropping.is_none() {
+                    ret.push_str(&escape(&decode_entities(&text)));
+                }
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+
+        if let Some(dropped) = &dropping {
+            if tag.closing && &tag.name == dropped {
+                dropping = None;
+            }
+            continue;
+        }
+        if !tag.closing && DROPPED_TAGS.contains(&tag.name.as_str()) {
+            dropping = Some(tag.name);
+            continue;
+        }
+        if !ALLOWED_TAGS.contains(&tag.name.as_str()) {
+            continue;
+        }
+
+        if tag.closing {
+            // Close the tags that were opened after this one as well
+            if let Some(i) = open_tags.iter().rposition(|name| name == &tag.name) {
+                for name in open_tags.drain(i..).rev() {
+                    ret.push_str(&format!("</{}>", name));
+                }
+            }
+        } else if VOID_TAGS.contains(&tag.name.as_str()) {
+            ret.push_str(&format!("<{} />", tag.name));
+        } else {
+            match tag.attribute("href").filter(|href| is_safe_url(href)) {
+                Some(href) if tag.name == "a" => {
+                    ret.push_str(&format!("<a href=\"{}\">", escape(href)))
+                }
+                _ => ret.push_str(&format!("<{}>", tag.name)),
+            }
+            open_tags.push(tag.name);
+        }
+    }
+
+    for name in open_tags.iter().rev() {
+        ret.push_str(&format!("</{}>", name));
+    }
+    ret
+}
+
+// The text of the HTML, with a line for every line break, paragraph or list
+// item
+pub fn to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut dropping: Option<String> = None;
+
+    for token in Tokenizer::new(html) {
+        match token {
+            Token::Text(value) => {
+                if dropping.is_none() {
+                    text.push_str(&decode_entities(&collapse_whitespace(&value)));
+                }
+            }
+            Token::Tag(tag) => {
+                if let Some(dropped) = &dropping {
+                    if tag.closing && &tag.name == dropped {
+                        dropping = None;
+                    }
+                } else if !tag.closing && DROPPED_TAGS.contains(&tag.name.as_str()) {
+                    dropping = Some(tag.name);
+                } else if tag.name == "br" {
+                    text.push('\n');
+                } else if tag.name == "li" {
+                    if !tag.closing {
+                        text.push_str("\n- ");
+                    }
+                } else if BLOCK_TAGS.contains(&tag.name.as_str()) {
+                    text.push('\n');
+                }
+            }
+        }
+    }
+
+    // Trim the lines and keep at most one empty line between paragraphs
+    let mut lines: Vec<&str> = vec![];
+    for line in text.lines().map(str::trim) {
+        if !line.is_empty() || lines.last().map(|last| !last.is_empty()).unwrap_or(false) {
+            lines.push(line);
+        }
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+// Whitespace in HTML is shown as a single space
+fn collapse_whitespace(text: &str) -> String {
+    let mut ret = String::new();
+    for c in text.chars() {
+        if !c.is_ascii_whitespace() {
+            ret.push(c);
+        } else if !ret.ends_with(' ') {
+            ret.push(' ');
+        }
+    }
+    ret
+}
+
+// Replaces the named and numeric character references by the characters
+// themselves. Unknown references are kept as they are.
+pub fn decode_entities(text: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        ret.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+        match decoded {
+            Some((c, length)) => {
+                ret.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                ret.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    ret.push_str(rest);
+    ret
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return std::char::from_u32(code).filter(|&c| c != '\0');
+    }
+
+    // The usual ones, and the accents used in Dutch, French and German
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "euro" => '€',
+        "deg" => '°',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "szlig" => 'ß',
+        "agrave" => 'à',
+        "aacute" => 'á',
+        "acirc" => 'â',
+        "auml" => 'ä',
+        "ccedil" => 'ç',
+        "egrave" => 'è',
+        "eacute" => 'é',
+        "ecirc" => 'ê',
+        "euml" => 'ë',
+        "icirc" => 'î',
+        "iuml" => 'ï',
+        "ocirc" => 'ô',
+        "ouml" => 'ö',
+        "ugrave" => 'ù',
+        "ucirc" => 'û',
+        "uuml" => 'ü',
+        "Agrave" => 'À',
+        "Auml" => 'Ä',
+        "Ccedil" => 'Ç',
+        "Egrave" => 'È',
+        "Eacute" => 'É',
+        "Ecirc" => 'Ê',
+        "Ouml" => 'Ö',
+        "Uuml" => 'Ü',
+        _ => return None,
+    };
+    Some(c)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Links to javascript: and the like are not kept
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    url.starts_with("http://") || url.starts_with("https://") || url.starts_with("mailto:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("Br&uuml;ssel &amp; Li&egrave;ge"), "Brüssel & Liège");
+        assert_eq!(decode_entities("&#233;&#xE9;&#XE9;"), "ééé");
+        assert_eq!(decode_entities("&lt;br /&gt;"), "<br />");
+        // Not references
+        assert_eq!(decode_entities("A & B &unknown; &#xZZ; &amp"), "A & B &unknown; &#xZZ; &amp");
+        assert_eq!(decode_entities("&#0;"), "&#0;");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("A<br />B<br>"), "A<br />B<br />");
+        assert_eq!(sanitize("<B class=\"x\">bold</B>"), "<b>bold</b>");
+        assert_eq!(sanitize("<div onclick=\"x()\">text</div>"), "text");
+        assert_eq!(sanitize("1 < 2 &amp; 3 > 2"), "1 &lt; 2 &amp; 3 &gt; 2");
+        assert_eq!(sanitize("&eacute;"), "é");
+        assert_eq!(sanitize("<!-- comment -->text"), "text");
+    }
+
+    #[test]
+    fn test_sanitize_dropped() {
+        assert_eq!(sanitize("a<script>alert('<b>')</script>b"), "ab");
+        assert_eq!(sanitize("a<style>p {}</style>b"), "ab");
+        assert_eq!(sanitize("a<img src=x onerror=alert(1)>b"), "ab");
+        // An unterminated tag is not closed by the text after it
+        assert_eq!(sanitize("a<b onclick=\"x()>b"), "a");
+    }
+
+    #[test]
+    fn test_sanitize_links() {
+        assert_eq!(
+            sanitize("<a href=\"https://nmbs.be/?a=1&amp;b=2\" target=\"_blank\">info</a>"),
+            "<a href=\"https://nmbs.be/?a=1&amp;b=2\">info</a>",
+        );
+        assert_eq!(sanitize("<a href=\"javascript:alert(1)\">info</a>"), "<a>info</a>");
+        assert_eq!(sanitize("<a href=' JaVaScRiPt:x'>info</a>"), "<a>info</a>");
+    }
+
+    #[test]
+    fn test_sanitize_nesting() {
+        // Tags that are left open are closed
+        assert_eq!(sanitize("<ul><li>a<li>b"), "<ul><li>a<li>b</li></li></ul>");
+        assert_eq!(sanitize("<b><i>a</b>"), "<b><i>a</i></b>");
+        // Closing tags without an opening tag are dropped
+        assert_eq!(sanitize("a</p></b>"), "a");
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(
+            to_text("Tussen Bosvoorde en Groenendaal:<br />Vertragingen zijn mogelijk.<br />"),
+            "Tussen Bosvoorde en Groenendaal:\nVertragingen zijn mogelijk.",
+        );
+        assert_eq!(to_text("<p>a\n  b</p><p>c &amp; d</p>"), "a b\n\nc & d");
+        assert_eq!(to_text("Info:<ul><li>a</li><li>b</li></ul>"), "Info:\n\n- a\n- b");
+        assert_eq!(to_text("a<script>x</script> <b>b</b>"), "a b");
+        assert_eq!(to_text("a <b>b</b>&nbsp;c"), "a b\u{a0}c");
+        assert_eq!(to_text("a<br /><br /><br /><br />b"), "a\n\nb");
+    }
+}
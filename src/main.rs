@@ -1,215 +1,492 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 mod gtfs_realtime;
+mod added_trip;
+mod config;
+mod feed_source;
+mod filter;
+mod himmatch;
+mod html;
 mod delay;
+mod realtime;
+mod realtime_export;
+mod retry;
+mod schedule;
+mod search;
+mod shape;
+mod vehicle;
+mod delaymap_alert;
+mod delaymap_geojson;
+mod delaymap_geometry;
+mod delaymap_liveboard;
+mod delaymap_occupancy;
 mod delaymap_stop_time;
 mod delaymap_train;
 mod delaymap_stop;
+mod delaymap_status;
 mod delaymap_works;
 mod delaymap_works_parser;
 
-use delay::Delay;
+use config::Config;
+use delaymap_alert::DelayMapAlert;
+use delaymap_geojson::DelayMapFormatted;
+use delaymap_liveboard::DelayMapLiveboard;
+use delaymap_status::DelayMapStatus;
+use delaymap_stop::DelayMapStop;
+use delaymap_status::FetchOutcome;
 use delaymap_train::DelayMapTrain;
 use delaymap_works::DelayMapWorks;
 use delaymap_works_parser::DelayMapWorksParser;
+use delaymap_works_parser::StationIndex;
+use filter::StopFilter;
+use filter::TrainFilter;
+use realtime::RealtimeResponse;
+use realtime::RealtimeSnapshot;
+use realtime_export::export_feed;
+use realtime_export::FeedResponse;
+use retry::retry_with_backoff;
+use schedule::rides_now;
+use shape::shape_lines;
+use shape::trip_shape;
+use shape::ShapeLine;
 
 use gtfs_structures::Translatable;
-use gtfs_realtime::FeedMessage;
 
-use chrono::NaiveDate;
+use chrono::DateTime;
 use chrono::TimeZone;
-use chrono::Timelike;
 use chrono::Utc;
-use chrono_tz::Europe::Brussels;
 
-use gtfs_structures::Exception;
 use gtfs_structures::Gtfs;
 use gtfs_structures::Trip;
 
 use lazy_static::lazy_static;
 
 use rocket::*;
+use rocket::request::LenientForm;
 use rocket_contrib::json::Json;
 
-use protobuf::Message;
-
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::sync::RwLock;
 use std::thread;
 use std::time::Duration;
 
 lazy_static! {
-    static ref GTFS: RwLock<Gtfs> = {
-        let gtfs = Gtfs::from_url(
-            "https://sncb-opendata.hafas.de/gtfs/static/c21ac6758dd25af84cca5b707f3cb3de",
+    static ref CONFIG: Config = Config::load();
+    // Empty until the first static feed has been loaded by update_gtfs
+    static ref GTFS: RwLock<Gtfs> = RwLock::new(Gtfs::default());
+    // The shapes and stations of GTFS, replaced together with it
+    static ref SHAPES: RwLock<HashMap<String, ShapeLine>> = RwLock::new(HashMap::new());
+    static ref STATIONS: RwLock<StationIndex> = RwLock::new(StationIndex::default());
+    static ref REALTIME: RwLock<RealtimeSnapshot> = RwLock::new(RealtimeSnapshot::empty());
+    static ref STATIC_STATUS: RwLock<FetchOutcome> = RwLock::new(FetchOutcome::default());
+    static ref REALTIME_STATUS: RwLock<FetchOutcome> = RwLock::new(FetchOutcome::default());
+    static ref WORKS_STATUS: RwLock<BTreeMap<&'static str, FetchOutcome>> = RwLock::new(BTreeMap::new());
+    // The works in every language, the English ones are linked to the trains
+    static ref WORKS: RwLock<HashMap<&'static str, Vec<DelayMapWorks>>> = RwLock::new(HashMap::new());
+    // The ids of the trips affected by each works, updated with the works and
+    // the realtime feed
+    static ref AFFECTED_TRIPS: RwLock<HashMap<String, Vec<String>>> = RwLock::new(HashMap::new());
+}
+
+// The moment given in seconds since the epoch, or now if there is none
+fn moment(at: Option<i64>) -> DateTime<Utc> {
+    at.and_then(|at| Utc.timestamp_opt(at, 0).single())
+        .unwrap_or_else(Utc::now)
+}
+
+// Adds the route of the train if the geometry was asked for
+fn add_geometry(train: DelayMapTrain, shape: Option<&ShapeLine>, geometry: Option<bool>) -> DelayMapTrain {
+    if geometry.unwrap_or(false) {
+        train.with_geometry(shape)
+    } else {
+        train
+    }
+}
+
+// The languages the works are downloaded in
+const WORKS_LANGUAGES: [&str; 4] = ["nl", "en", "fr", "de"];
+
+// The language of the works to use for the given language, English if the
+// works are not available in it
+fn works_language(language: Option<&str>) -> &'static str {
+    WORKS_LANGUAGES
+        .iter()
+        .find(|&&works_language| Some(works_language) == language)
+        .unwrap_or(&"en")
+}
+
+// The works the trains are linked to
+fn linked_works<'a>(works: &'a HashMap<&'static str, Vec<DelayMapWorks>>) -> &'a [DelayMapWorks] {
+    works.get("en").map(Vec::as_slice).unwrap_or(&[])
+}
+
+// Builds the train of the trip. The times of added trips are already the
+// predicted ones, so only their vehicle is used.
+fn create_train(
+    trip: &Trip,
+    realtime: &RealtimeSnapshot,
+    shape: Option<&ShapeLine>,
+    now: DateTime<Utc>,
+) -> DelayMapTrain {
+    match realtime.added_trips.get(&trip.id) {
+        Some(added_trip) => DelayMapTrain::from_trip_delay(trip, Some(&added_trip.trip_delay()), shape, now),
+        None => DelayMapTrain::from_gtfs(trip, &realtime.delays, shape, now),
+    }
+}
+
+// The trips riding at the given moment that are kept, including the ones that
+// are only in the realtime feed
+fn riding_trips(
+    gtfs: &Gtfs,
+    realtime: &RealtimeSnapshot,
+    language: &str,
+    now: DateTime<Utc>,
+    keep: impl Fn(&Trip) -> bool,
+) -> Vec<Trip> {
+    gtfs.trips
+        .values()
+        .filter(|trip| keep(trip))
+        .map(|trip| trip.translate(gtfs, language))
+        .filter(|trip| rides_now(gtfs, trip, now))
+        .chain(
+            realtime.added_trips
+                .values()
+                .filter(|added_trip| added_trip.rides_now(now))
+                .filter_map(|added_trip| added_trip.to_trip(gtfs))
+                .filter(|trip| keep(trip)),
         )
-        .expect("Invalid GTFS url");
-        RwLock::new(gtfs)
+        .collect()
+}
+
+// The riding trips with their trains, linked to the works that affect them
+fn riding_trains(
+    gtfs: &Gtfs,
+    realtime: &RealtimeSnapshot,
+    language: &str,
+    now: DateTime<Utc>,
+    geometry: Option<bool>,
+    keep: impl Fn(&Trip) -> bool,
+) -> Vec<(Trip, DelayMapTrain)> {
+    let shapes = SHAPES.read().unwrap();
+    let works = WORKS.read().unwrap();
+    riding_trips(gtfs, realtime, language, now, keep)
+        .into_iter()
+        .map(|trip| {
+            let shape = trip_shape(&shapes, &trip);
+            let train = create_train(&trip, &realtime, shape, now);
+            let train = add_geometry(train, shape, geometry).with_works(&trip, linked_works(&works), now);
+            (trip, train)
+        })
+        .collect()
+}
+
+#[get("/trains?<language>&<at>&<geometry>&<format>&<filter..>")]
+fn trains(
+    language: Option<String>,
+    at: Option<i64>,
+    geometry: Option<bool>,
+    format: Option<String>,
+    filter: LenientForm<TrainFilter>,
+) -> RealtimeResponse<DelayMapFormatted<DelayMapTrain>> {
+    let now = moment(at);
+    let gtfs = GTFS.read().unwrap();
+    let realtime = REALTIME.read().unwrap();
+    let language = language.unwrap_or("en".to_string());
+    RealtimeResponse::new(
+        DelayMapFormatted::new(
+            riding_trains(&gtfs, &realtime, &language, now, geometry, |trip| filter.matches_trip(&gtfs, trip))
+                .into_iter()
+                .map(|(_, train)| train)
+                .filter(|train| filter.matches_train(train))
+                .collect(),
+            format,
+            |train| Some((train.estimated_lat, train.estimated_lon)),
+        ),
+        &realtime,
+    )
+}
+
+#[get("/trains/<id>?<language>&<at>&<geometry>")]
+fn train(
+    id: String,
+    language: Option<String>,
+    at: Option<i64>,
+    geometry: Option<bool>,
+) -> Option<RealtimeResponse<Json<DelayMapTrain>>> {
+    let gtfs = GTFS.read().unwrap();
+    let realtime = REALTIME.read().unwrap();
+    let now = moment(at);
+    let (translated_trip, riding) = match gtfs.trips.get(&id) {
+        Some(trip) => {
+            let translated_trip = trip.translate(&gtfs, &language.unwrap_or("en".to_string()));
+            let riding = rides_now(&gtfs, &translated_trip, now);
+            (translated_trip, riding)
+        }
+        None => {
+            let added_trip = realtime.added_trips.get(&id)?;
+            (added_trip.to_trip(&gtfs)?, added_trip.rides_now(now))
+        }
     };
+    let shapes = SHAPES.read().unwrap();
+    let shape = trip_shape(&shapes, &translated_trip);
+    let mut train = create_train(&translated_trip, &realtime, shape, now);
+    train.riding = riding;
+    Some(RealtimeResponse::new(
+        Json(
+            add_geometry(train, shape, geometry)
+                .with_works(&translated_trip, linked_works(&WORKS.read().unwrap()), now),
+        ),
+        &realtime,
+    ))
 }
 
-#[get("/trains?<language>")]
-fn trains(language: Option<String>) -> Json<Vec<DelayMapTrain>> {
+#[get("/gtfs-rt?<at>&<format>")]
+fn gtfs_rt(at: Option<i64>, format: Option<String>) -> RealtimeResponse<FeedResponse> {
+    let now = moment(at);
     let gtfs = GTFS.read().unwrap();
-    let delays = get_delays();
-    Json(
-        gtfs.trips
-            .values()
-            .filter_map(|trip| {
-                let translated_trip = trip.translate(&gtfs, &language.clone().unwrap_or("en".to_string()));
-                if rides_now(&gtfs, &translated_trip) {
-                    Some(DelayMapTrain::from_gtfs(&translated_trip, &delays))
-                } else {
-                    None
-                }
-            })
-            .collect(),
+    let realtime = REALTIME.read().unwrap();
+    let trains = riding_trains(&gtfs, &realtime, "en", now, None, |_| true);
+    RealtimeResponse::new(
+        FeedResponse::new(export_feed(&trains, &realtime, now), format),
+        &realtime,
     )
 }
 
-#[get("/works?<language>")]
-fn works(language: Option<String>) -> Json<Vec<DelayMapWorks>> {
-    let language_path = match language.as_ref().map(String::as_str) {
-        Some("nl") => "nny",
-        Some("en") => "eny",
-        Some("fr") => "fny",
-        Some("de") => "dny",
-        None => "eny",
+#[get("/stops?<language>&<format>&<filter..>")]
+fn stops(
+    language: Option<String>,
+    format: Option<String>,
+    filter: LenientForm<StopFilter>,
+) -> DelayMapFormatted<DelayMapStop> {
+    let gtfs = GTFS.read().unwrap();
+    let language = language.unwrap_or("en".to_string());
+    let stops = gtfs.stops
+        .keys()
+        .filter_map(|id| gtfs.get_stop_translated(id, &language).ok())
+        .map(|stop| stop.into())
+        .collect();
+    DelayMapFormatted::new(filter.apply(stops), format, |stop| Some((stop.lat?, stop.lon?)))
+}
+
+#[get("/liveboard/<id>?<language>&<minutes>")]
+fn liveboard(
+    id: String,
+    language: Option<String>,
+    minutes: Option<u32>,
+) -> Option<RealtimeResponse<Json<DelayMapLiveboard>>> {
+    let gtfs = GTFS.read().unwrap();
+    let realtime = REALTIME.read().unwrap();
+    let language = language.unwrap_or("en".to_string());
+    let stop = gtfs.get_stop_translated(&id, &language).ok()?;
+    Some(RealtimeResponse::new(
+        Json(DelayMapLiveboard::from_gtfs(
+            &gtfs,
+            stop,
+            &language,
+            &realtime.delays,
+            Utc::now(),
+            chrono::Duration::minutes(i64::from(minutes.unwrap_or(60))),
+        )),
+        &realtime,
+    ))
+}
+
+#[get("/alerts?<language>")]
+fn alerts(language: Option<String>) -> RealtimeResponse<Json<Vec<DelayMapAlert>>> {
+    let gtfs = GTFS.read().unwrap();
+    let realtime = REALTIME.read().unwrap();
+    let language = language.unwrap_or("en".to_string());
+    let mut alerts: Vec<DelayMapAlert> = realtime.alerts
+        .iter()
+        .map(|(id, alert)| DelayMapAlert::from_gtfs(&gtfs, id, alert, &language))
+        .collect();
+    alerts.sort_by(|a, b| a.id.cmp(&b.id));
+    RealtimeResponse::new(Json(alerts), &realtime)
+}
+
+// The location of works is the location of the impacted station
+fn works_location(works: &DelayMapWorks) -> Option<(f64, f64)> {
+    let station = works.impacted_station.as_ref()?;
+    Some((station.lat?, station.lon?))
+}
+
+// Downloads and parses the works. The works before a parse error are still
+// returned.
+fn fetch_works(language: &'static str) -> Result<Vec<DelayMapWorks>, reqwest::Error> {
+    let language_path = match language {
+        "nl" => "nny",
+        "fr" => "fny",
+        "de" => "dny",
         _ => "eny",
     };
 
-    let response_res = reqwest::blocking::get(
+    // Error responses and bodies that cannot be read are failures as well
+    let content_res = reqwest::blocking::get(
         format!("http://www.belgianrail.be/jp/nmbs-realtime/query.exe/{}?performLocating=512&tpl=himmatch2json&look_nv=type|himmatch|maxnumber|300|no_match|yes|pubchannels|custom1|1028|", language_path),
-    );
+    )
+    .and_then(|response| response.error_for_status())
+    .and_then(|response| response.text());
 
-    if response_res.is_err() {
-        return Json(vec!());
+    if let Err(e) = &content_res {
+        eprintln!("Failed to download works: {}", e);
+        WORKS_STATUS.write().unwrap().entry(language).or_default().failed(e, Utc::now());
     }
-
-    let response = response_res.unwrap();
+    let content = content_res?;
+    WORKS_STATUS.write().unwrap().entry(language).or_default().succeeded(Utc::now());
 
     let mut ret = vec!();
 
-    let content = response.text().unwrap_or("".to_string());
-
     let mut found_works = true;
-    let mut parser = DelayMapWorksParser::new(language, content);
+    let mut parser = DelayMapWorksParser::new(Some(language.to_string()), content);
     while found_works {
         // TODO: If this fails, we need to recreate the rwlock
         let gtfs = GTFS.read().unwrap();
 
-        let res_new_works = parser.parse_next(gtfs);
-        if let Ok(Some(new_works)) = res_new_works {
-            ret.push(new_works);
-        } else {
-            found_works = false;
+        match parser.parse_next(gtfs, &SHAPES.read().unwrap(), &STATIONS.read().unwrap()) {
+            Ok(Some(new_works)) => ret.push(new_works),
+            Ok(None) => found_works = false,
+            Err(e) => {
+                eprintln!("Failed to parse works: {}", e);
+                // The works before the error are still used, so the
+                // download counts as a success with an error
+                WORKS_STATUS.write().unwrap().entry(language).or_default().failed(&e, Utc::now());
+                found_works = false;
+            }
         }
     }
 
-    return Json(
-        ret
-    );
+    Ok(ret)
 }
 
-fn rides_now(gtfs: &Gtfs, trip: &Trip) -> bool {
-    let local_datetime = Brussels.from_utc_datetime(&Utc::now().naive_utc());
-    let local_date: NaiveDate = local_datetime.date().naive_local();
-    let local_timestamp = local_datetime.time().num_seconds_from_midnight();
-
-    let trip_starting_timestamp = trip.stop_times[0].departure_time;
-    let trip_ending_timestamp = trip.stop_times.last().unwrap().arrival_time;
+#[get("/works?<language>&<format>")]
+fn works(language: Option<String>, format: Option<String>) -> DelayMapFormatted<DelayMapWorks> {
+    let language = works_language(language.as_ref().map(String::as_str));
+    let mut works = WORKS.read().unwrap().get(language).cloned().unwrap_or_default();
 
-    // if it has no start or end timestamp, assume it doesn't ride
-    if trip_starting_timestamp.is_none() || trip_ending_timestamp.is_none() {
-        return false;
+    let affected_trips = AFFECTED_TRIPS.read().unwrap();
+    for entry in works.iter_mut() {
+        entry.affected_trips = affected_trips.get(&entry.id).cloned().unwrap_or_default();
     }
 
-    // If it rides today and at this time, it is currently on the road
-    if rides_at_date(gtfs, trip, local_date)
-        && trip_starting_timestamp.unwrap() <= local_timestamp
-        && trip_ending_timestamp.unwrap() >= local_timestamp
-    {
-        return true;
-    }
-
-    // If it rode yesterday, but after midnight it could still be on the road
-    if rides_at_date(gtfs, trip, local_date.pred())
-        && trip_ending_timestamp.unwrap() >= (24 * 60 * 60)
-    {
-        if trip_starting_timestamp.unwrap() < 24 * 60 * 60 {
-            return trip_starting_timestamp.unwrap() <= local_timestamp
-                || trip_ending_timestamp.unwrap() - 24 * 60 * 60 >= local_timestamp;
-        } else {
-            return trip_starting_timestamp.unwrap() - 24 * 60 * 60 <= local_timestamp
-                && trip_ending_timestamp.unwrap() - 24 * 60 * 60 >= local_timestamp;
-        }
-    }
+    return DelayMapFormatted::new(works, format, works_location);
+}
 
-    return false;
+#[get("/status")]
+fn status() -> Json<DelayMapStatus> {
+    let gtfs = GTFS.read().unwrap();
+    let realtime = REALTIME.read().unwrap();
+    Json(DelayMapStatus::new(
+        &gtfs,
+        &STATIC_STATUS.read().unwrap(),
+        &realtime,
+        &REALTIME_STATUS.read().unwrap(),
+        &WORKS_STATUS.read().unwrap(),
+    ))
 }
 
-fn rides_at_date(gtfs: &Gtfs, trip: &Trip, date: NaiveDate) -> bool {
-    let mut ret = false;
+// Links the works to the trips they affect now. Only the stop the train is at
+// is needed, so the trains are built without their shape.
+fn link_works() {
+    let now = Utc::now();
+    let affected_trips = {
+        let gtfs = GTFS.read().unwrap();
+        let realtime = REALTIME.read().unwrap();
+        let works = WORKS.read().unwrap();
+        let trains: Vec<(Trip, DelayMapTrain)> = riding_trips(&gtfs, &realtime, "en", now, |_| true)
+            .into_iter()
+            .map(|trip| {
+                let train = create_train(&trip, &realtime, None, now);
+                (trip, train)
+            })
+            .collect();
+        linked_works(&works)
+            .iter()
+            .map(|entry| {
+                let mut trip_ids: Vec<String> = trains
+                    .iter()
+                    .filter(|(trip, train)| entry.affects(trip, train, now))
+                    .map(|(trip, _)| trip.id.clone())
+                    .collect();
+                trip_ids.sort();
+                (entry.id.clone(), trip_ids)
+            })
+            .collect()
+    };
+    *AFFECTED_TRIPS.write().unwrap() = affected_trips;
+}
 
-    // Check if it rides in a normal schedule
-    if let Some(calendar) = gtfs.calendar.get(&trip.service_id) {
-        if calendar.start_date <= date && calendar.end_date >= date && calendar.valid_weekday(date)
-        {
-            ret = true;
+fn update_realtime() {
+    match RealtimeSnapshot::fetch(&CONFIG.realtime_source()) {
+        Ok(snapshot) => {
+            *REALTIME.write().unwrap() = snapshot;
+            REALTIME_STATUS.write().unwrap().succeeded(Utc::now());
         }
-    }
-
-    // Check if there are exceptions today
-    for extra_day in gtfs
-        .calendar_dates
-        .get(&trip.service_id)
-        .iter()
-        .flat_map(|e| e.iter())
-    {
-        if extra_day.date == date {
-            if extra_day.exception_type == Exception::Added {
-                ret = true;
-            } else if extra_day.exception_type == Exception::Deleted {
-                ret = false;
-            }
+        Err(e) => {
+            eprintln!("Failed to update realtime feed: {}", e);
+            REALTIME_STATUS.write().unwrap().failed(&e, Utc::now());
+            REALTIME.write().unwrap().fetch_failed(
+                &e,
+                chrono::Duration::seconds(CONFIG.realtime_max_age as i64),
+                Utc::now(),
+            );
         }
     }
-
-    ret
+    link_works();
 }
 
-fn get_delays() -> HashMap<String, HashMap<String, Delay>> {
-    let mut response = reqwest::blocking::get(
-        "https://sncb-opendata.hafas.de/gtfs/realtime/c21ac6758dd25af84cca5b707f3cb3de",
-    )
-    .unwrap();
-    let feed = FeedMessage::parse_from_reader(&mut response).unwrap();
-
-    let mut ret = HashMap::new();
-
-    for entity in feed.entity {
-        if let Some(update) = entity.trip_update.into_option() {
-            if let Some(trip) = update.trip.into_option() {
-                let key = trip.get_trip_id();
-                let mut delay_map: HashMap<String, Delay> = HashMap::new();
-                for update in update.stop_time_update {
-                    let stop_id = update.get_stop_id().to_string();
-                    delay_map.insert(stop_id, update.into());
-                }
-                ret.insert(key.to_string(), delay_map);
-            }
+fn update_works() {
+    for &language in WORKS_LANGUAGES.iter() {
+        // Keep the previous works if they could not be downloaded
+        if let Ok(works) = fetch_works(language) {
+            WORKS.write().unwrap().insert(language, works);
         }
     }
-
-    ret
+    link_works();
 }
 
 fn update_gtfs() {
-    let mut gtfs = GTFS.write().unwrap();
-    *gtfs = Gtfs::from_url(
-        "https://sncb-opendata.hafas.de/gtfs/static/c21ac6758dd25af84cca5b707f3cb3de",
-    )
-    .expect("Invalid GTFS url");
+    // Load the new feed without holding the lock, so requests can still be
+    // answered using the previous one in the meantime.
+    let res = retry_with_backoff(
+        CONFIG.static_retries,
+        Duration::new(CONFIG.static_retry_backoff, 0),
+        thread::sleep,
+        || {
+            let res = CONFIG.static_source().load_gtfs();
+            if let Err(e) = &res {
+                eprintln!("Failed to load GTFS: {}", e);
+                STATIC_STATUS.write().unwrap().failed(e, Utc::now());
+            }
+            res
+        },
+    );
+
+    match res {
+        Ok(gtfs) => {
+            let shapes = shape_lines(&gtfs);
+            let stations = StationIndex::new(&gtfs);
+            let (old_gtfs, old_shapes, old_stations) = {
+                let mut gtfs_lock = GTFS.write().unwrap();
+                let mut shapes_lock = SHAPES.write().unwrap();
+                let mut stations_lock = STATIONS.write().unwrap();
+                (
+                    std::mem::replace(&mut *gtfs_lock, gtfs),
+                    std::mem::replace(&mut *shapes_lock, shapes),
+                    std::mem::replace(&mut *stations_lock, stations),
+                )
+            };
+            // Only free the old feed once the locks are released
+            drop(old_gtfs);
+            drop(old_shapes);
+            drop(old_stations);
+            STATIC_STATUS.write().unwrap().succeeded(Utc::now());
+
+            // The stations of the works are looked up in the static feed
+            update_works();
+        }
+        Err(_) => eprintln!("Giving up, keeping the previous GTFS"),
+    }
 }
 
 fn main() {
@@ -218,15 +495,32 @@ fn main() {
             println!("Updating");
             update_gtfs();
             println!("Done updating");
-            thread::sleep(Duration::new(24 * 60 * 60, 0));
+            thread::sleep(Duration::new(CONFIG.static_interval, 0));
         }
     });
 
-    let cors = rocket_cors::CorsOptions::default()
-        .to_cors()
-        .expect("Invalid CORS settings");
+    thread::spawn(move || {
+        loop {
+            update_realtime();
+            thread::sleep(Duration::new(CONFIG.realtime_interval, 0));
+        }
+    });
+
+    thread::spawn(move || {
+        loop {
+            update_works();
+            thread::sleep(Duration::new(CONFIG.works_interval, 0));
+        }
+    });
+
+    let cors = rocket_cors::CorsOptions {
+        expose_headers: ["X-Feed-Timestamp", "X-Realtime-Status"].iter().map(|h| h.to_string()).collect(),
+        ..Default::default()
+    }
+    .to_cors()
+    .expect("Invalid CORS settings");
     rocket::ignite()
-        .mount("/", routes![trains, works])
+        .mount("/", routes![trains, train, gtfs_rt, stops, liveboard, alerts, works, status])
         .attach(cors)
         .launch();
 }
@@ -0,0 +1,437 @@
+use crate::added_trip::AddedTrip;
+use crate::delay::TripDelay;
+use crate::feed_source::FeedError;
+use crate::feed_source::FeedSource;
+use crate::gtfs_realtime::Alert;
+use crate::gtfs_realtime::FeedMessage;
+use crate::gtfs_realtime::TripDescriptor_ScheduleRelationship;
+use crate::vehicle::Vehicle;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use protobuf::Message;
+
+use rocket::request::Request;
+use rocket::response;
+use rocket::response::Responder;
+
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+// The most recently fetched realtime feed, mapping trip ids to the delays of
+// their stops. Trips that are not in the static feed are kept separately.
+#[derive(Debug, Clone)]
+pub struct RealtimeSnapshot {
+    pub delays: HashMap<String, TripDelay>,
+    pub added_trips: HashMap<String, AddedTrip>,
+    // Keyed by the id of their feed entity
+    pub alerts: HashMap<String, Alert>,
+    pub feed_timestamp: Option<u64>,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RealtimeStatus {
+    // The last download of the realtime feed succeeded
+    Fresh,
+    // The last download failed, the data of an earlier download is used
+    Stale,
+    // There is no realtime data, only the schedule is used
+    Missing,
+}
+
+impl RealtimeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RealtimeStatus::Fresh => "fresh",
+            RealtimeStatus::Stale => "stale",
+            RealtimeStatus::Missing => "missing",
+        }
+    }
+}
+
+impl RealtimeSnapshot {
+    pub fn empty() -> Self {
+        RealtimeSnapshot {
+            delays: HashMap::new(),
+            added_trips: HashMap::new(),
+            alerts: HashMap::new(),
+            feed_timestamp: None,
+            fetched_at: None,
+            last_error: None,
+        }
+    }
+
+    pub fn fetch(source: &FeedSource) -> Result<Self, FeedError> {
+        let mut reader = source.open()?;
+        let feed = FeedMessage::parse_from_reader(&mut reader)?;
+        let mut snapshot: RealtimeSnapshot = feed.into();
+        snapshot.fetched_at = Some(Utc::now());
+        Ok(snapshot)
+    }
+
+    pub fn status(&self) -> RealtimeStatus {
+        if self.fetched_at.is_none() {
+            RealtimeStatus::Missing
+        } else if self.last_error.is_some() {
+            RealtimeStatus::Stale
+        } else {
+            RealtimeStatus::Fresh
+        }
+    }
+
+    // Remember that updating failed. The delays are kept until they are older
+    // than max_age, after which only the schedule is used.
+    pub fn fetch_failed(&mut self, error: &FeedError, max_age: Duration, now: DateTime<Utc>) {
+        self.last_error = Some(error.to_string());
+
+        if let Some(fetched_at) = self.fetched_at {
+            if now - fetched_at > max_age {
+                self.delays.clear();
+                self.added_trips.clear();
+                self.alerts.clear();
+                self.feed_timestamp = None;
+                self.fetched_at = None;
+            }
+        }
+    }
+}
+
+impl From<FeedMessage> for RealtimeSnapshot {
+    fn from(feed: FeedMessage) -> Self {
+        let mut delays = HashMap::new();
+        let mut added_trips: HashMap<String, AddedTrip> = HashMap::new();
+        let mut vehicles: Vec<(String, Vehicle)> = vec![];
+        let mut alerts = HashMap::new();
+
+        let header = feed.get_header();
+        let feed_timestamp = if header.has_timestamp() {
+            Some(header.get_timestamp())
+        } else {
+            None
+        };
+
+        for entity in feed.entity {
+            let id = entity.get_id().to_string();
+            if let Some(alert) = entity.alert.into_option() {
+                alerts.insert(id, alert);
+            }
+
+            if let Some(vehicle_position) = entity.vehicle.into_option() {
+                if vehicle_position.has_trip() {
+                    let key = vehicle_position.get_trip().get_trip_id().to_string();
+                    vehicles.push((key, vehicle_position.into()));
+                }
+            }
+
+            if let Some(update) = entity.trip_update.into_option() {
+                if !update.has_trip() {
+                    continue;
+                }
+                let key = update.get_trip().get_trip_id().to_string();
+                if update.get_trip().get_schedule_relationship()
+                    == TripDescriptor_ScheduleRelationship::ADDED
+                {
+                    added_trips.insert(key, update.into());
+                } else {
+                    delays.insert(key, update.into());
+                }
+            }
+        }
+
+        // Vehicles are added afterwards, as their trip update can come later.
+        // Without a timestamp of their own, they are as old as the feed.
+        for (key, mut vehicle) in vehicles {
+            vehicle.timestamp = vehicle.timestamp.or(feed_timestamp);
+            match added_trips.get_mut(&key) {
+                Some(added_trip) => added_trip.vehicle = Some(vehicle),
+                None => delays.entry(key).or_insert_with(TripDelay::default).vehicle = Some(vehicle),
+            }
+        }
+
+        RealtimeSnapshot {
+            delays,
+            added_trips,
+            alerts,
+            feed_timestamp,
+            fetched_at: None,
+            last_error: None,
+        }
+    }
+}
+
+// Turns a protobuf enum value like TECHNICAL_PROBLEM into technicalProblem
+pub fn enum_name<E: Debug>(value: E) -> String {
+    format!("{:?}", value)
+        .split('_')
+        .enumerate()
+        .map(|(i, word)| {
+            let word = word.to_lowercase();
+            if i == 0 {
+                word
+            } else {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+// Wraps a response to add information about the realtime snapshot it was
+// built from as headers, keeping the body unchanged.
+pub struct RealtimeResponse<R> {
+    inner: R,
+    feed_timestamp: Option<u64>,
+    status: RealtimeStatus,
+}
+
+impl<R> RealtimeResponse<R> {
+    pub fn new(inner: R, snapshot: &RealtimeSnapshot) -> Self {
+        RealtimeResponse {
+            inner,
+            feed_timestamp: snapshot.feed_timestamp,
+            status: snapshot.status(),
+        }
+    }
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for RealtimeResponse<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.inner.respond_to(request)?;
+        response.set_raw_header("X-Realtime-Status", self.status.as_str());
+        if let Some(feed_timestamp) = self.feed_timestamp {
+            response.set_raw_header("X-Feed-Timestamp", feed_timestamp.to_string());
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delay::Delay;
+    use crate::gtfs_realtime::Alert_Cause;
+    use crate::gtfs_realtime::Alert_Effect;
+    use crate::gtfs_realtime::FeedEntity;
+    use crate::gtfs_realtime::FeedHeader;
+    use crate::gtfs_realtime::Position;
+    use crate::gtfs_realtime::TripDescriptor;
+    use crate::gtfs_realtime::TripUpdate;
+    use crate::gtfs_realtime::TripUpdate_StopTimeEvent;
+    use crate::gtfs_realtime::TripUpdate_StopTimeUpdate;
+    use crate::gtfs_realtime::VehiclePosition;
+
+    use chrono::TimeZone;
+
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_enum_name() {
+        assert_eq!(enum_name(Alert_Cause::TECHNICAL_PROBLEM), "technicalProblem");
+        assert_eq!(enum_name(Alert_Effect::DETOUR), "detour");
+    }
+
+    #[test]
+    fn test_empty_feed() {
+        let snapshot: RealtimeSnapshot = FeedMessage::new().into();
+        assert!(snapshot.delays.is_empty());
+        assert_eq!(snapshot.feed_timestamp, None);
+        assert_eq!(snapshot.status(), RealtimeStatus::Missing);
+    }
+
+    #[test]
+    fn test_fetch_failed_keeps_recent() {
+        let fetched_at = Utc.ymd(2022, 6, 19).and_hms(14, 0, 0);
+        let mut snapshot = create_snapshot(fetched_at);
+        assert_eq!(snapshot.status(), RealtimeStatus::Fresh);
+
+        snapshot.fetch_failed(
+            &FeedError::Io(io::ErrorKind::TimedOut.into()),
+            Duration::minutes(5),
+            fetched_at + Duration::minutes(1),
+        );
+
+        assert_eq!(snapshot.status(), RealtimeStatus::Stale);
+        assert_eq!(snapshot.delays.len(), 1);
+        assert_eq!(snapshot.feed_timestamp, Some(1655647200));
+    }
+
+    #[test]
+    fn test_fetch_failed_drops_old() {
+        let fetched_at = Utc.ymd(2022, 6, 19).and_hms(14, 0, 0);
+        let mut snapshot = create_snapshot(fetched_at);
+
+        snapshot.fetch_failed(
+            &FeedError::Io(io::ErrorKind::TimedOut.into()),
+            Duration::minutes(5),
+            fetched_at + Duration::minutes(10),
+        );
+
+        assert_eq!(snapshot.status(), RealtimeStatus::Missing);
+        assert!(snapshot.delays.is_empty());
+        assert_eq!(snapshot.feed_timestamp, None);
+    }
+
+    #[test]
+    fn test_decode_error() {
+        let garbage: &[u8] = &[0xff, 0xff, 0xff];
+        let res = FeedMessage::parse_from_bytes(garbage).map_err(FeedError::from);
+        assert!(matches!(res, Err(FeedError::Decode(_))));
+    }
+
+    #[test]
+    fn test_trip_update() {
+        let mut header = FeedHeader::new();
+        header.set_timestamp(1655647200);
+
+        let mut departure = TripUpdate_StopTimeEvent::new();
+        departure.set_delay(120);
+        let mut stop_time_update = TripUpdate_StopTimeUpdate::new();
+        stop_time_update.set_stop_id("stop1".to_string());
+        stop_time_update.set_departure(departure);
+
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("my-train".to_string());
+        let mut trip_update = TripUpdate::new();
+        trip_update.set_trip(trip);
+        trip_update.mut_stop_time_update().push(stop_time_update);
+
+        let mut entity = FeedEntity::new();
+        entity.set_id("1".to_string());
+        entity.set_trip_update(trip_update);
+
+        let mut feed = FeedMessage::new();
+        feed.set_header(header);
+        feed.mut_entity().push(entity);
+
+        let snapshot: RealtimeSnapshot = feed.into();
+        assert_eq!(snapshot.feed_timestamp, Some(1655647200));
+        let delay = &snapshot.delays["my-train"].stops["stop1"];
+        assert_eq!(delay.departure_delay, Some(120));
+        assert_eq!(delay.arrival_delay, None);
+    }
+
+    #[test]
+    fn test_added_trip() {
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("extra".to_string());
+        trip.set_schedule_relationship(TripDescriptor_ScheduleRelationship::ADDED);
+        let mut trip_update = TripUpdate::new();
+        trip_update.set_trip(trip);
+
+        let mut entity = FeedEntity::new();
+        entity.set_id("1".to_string());
+        entity.set_trip_update(trip_update);
+
+        let mut feed = FeedMessage::new();
+        feed.mut_entity().push(entity);
+
+        let snapshot: RealtimeSnapshot = feed.into();
+        assert!(snapshot.delays.is_empty());
+        assert_eq!(snapshot.added_trips["extra"].id, "extra");
+    }
+
+    #[test]
+    fn test_added_trip_vehicle() {
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("extra".to_string());
+        let mut vehicle_position = VehiclePosition::new();
+        vehicle_position.set_trip(trip.clone());
+        let mut vehicle_entity = FeedEntity::new();
+        vehicle_entity.set_id("1".to_string());
+        vehicle_entity.set_vehicle(vehicle_position);
+
+        trip.set_schedule_relationship(TripDescriptor_ScheduleRelationship::ADDED);
+        let mut trip_update = TripUpdate::new();
+        trip_update.set_trip(trip);
+        let mut update_entity = FeedEntity::new();
+        update_entity.set_id("2".to_string());
+        update_entity.set_trip_update(trip_update);
+
+        let mut feed = FeedMessage::new();
+        feed.mut_entity().push(vehicle_entity);
+        feed.mut_entity().push(update_entity);
+
+        // The vehicle belongs to the added trip, not to a static one
+        let snapshot: RealtimeSnapshot = feed.into();
+        assert!(snapshot.delays.is_empty());
+        assert!(snapshot.added_trips["extra"].vehicle.is_some());
+    }
+
+    #[test]
+    fn test_vehicle_position() {
+        let mut position = Position::new();
+        position.set_latitude(51.0);
+        position.set_longitude(3.5);
+        let mut trip = TripDescriptor::new();
+        trip.set_trip_id("my-train".to_string());
+        let mut vehicle_position = VehiclePosition::new();
+        vehicle_position.set_trip(trip.clone());
+        vehicle_position.set_position(position);
+
+        let mut vehicle_entity = FeedEntity::new();
+        vehicle_entity.set_id("1".to_string());
+        vehicle_entity.set_vehicle(vehicle_position);
+
+        let mut trip_update = TripUpdate::new();
+        trip_update.set_trip(trip);
+        trip_update.mut_stop_time_update().push(TripUpdate_StopTimeUpdate::new());
+        let mut update_entity = FeedEntity::new();
+        update_entity.set_id("2".to_string());
+        update_entity.set_trip_update(trip_update);
+
+        let mut header = FeedHeader::new();
+        header.set_timestamp(1655647200);
+        let mut feed = FeedMessage::new();
+        feed.set_header(header);
+        feed.mut_entity().push(vehicle_entity);
+        feed.mut_entity().push(update_entity);
+
+        let snapshot: RealtimeSnapshot = feed.into();
+        let trip_delay = &snapshot.delays["my-train"];
+        assert_eq!(trip_delay.stops.len(), 1);
+        assert_eq!(trip_delay.vehicle.as_ref().unwrap().lat, Some(51.0));
+        // The vehicle has no timestamp, so the one of the feed is used
+        assert_eq!(trip_delay.vehicle.as_ref().unwrap().timestamp, Some(1655647200));
+    }
+
+    #[test]
+    fn test_alert() {
+        let mut entity = FeedEntity::new();
+        entity.set_id("alert1".to_string());
+        entity.set_alert(Alert::new());
+
+        let mut feed = FeedMessage::new();
+        feed.mut_entity().push(entity);
+
+        let snapshot: RealtimeSnapshot = feed.into();
+        assert!(snapshot.delays.is_empty());
+        assert!(snapshot.alerts.contains_key("alert1"));
+    }
+
+    // ----- HELPERS -----
+    fn create_snapshot(fetched_at: DateTime<Utc>) -> RealtimeSnapshot {
+        let mut trip_delay = TripDelay::default();
+        trip_delay.stops.insert("stop1".to_string(), Delay {
+            arrival_delay: None,
+            departure_delay: Some(60),
+            ..Default::default()
+        });
+
+        let mut snapshot = RealtimeSnapshot::empty();
+        snapshot.delays.insert("my-train".to_string(), trip_delay);
+        snapshot.feed_timestamp = Some(1655647200);
+        snapshot.fetched_at = Some(fetched_at);
+        snapshot
+    }
+}
@@ -0,0 +1,412 @@
+use crate::delaymap_train::DelayMapTrain;
+use crate::gtfs_realtime::FeedEntity;
+use crate::gtfs_realtime::FeedHeader;
+use crate::gtfs_realtime::FeedHeader_Incrementality;
+use crate::gtfs_realtime::FeedMessage;
+use crate::gtfs_realtime::Position;
+use crate::gtfs_realtime::TripDescriptor;
+use crate::gtfs_realtime::TripDescriptor_ScheduleRelationship;
+use crate::gtfs_realtime::TripUpdate;
+use crate::gtfs_realtime::TripUpdate_StopTimeEvent;
+use crate::gtfs_realtime::TripUpdate_StopTimeUpdate;
+use crate::gtfs_realtime::TripUpdate_StopTimeUpdate_ScheduleRelationship;
+use crate::gtfs_realtime::VehiclePosition;
+use crate::gtfs_realtime::VehiclePosition_VehicleStopStatus;
+use crate::realtime::RealtimeSnapshot;
+use crate::schedule::service_date_at;
+
+use gtfs_structures::Trip;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use protobuf::reflect::ReflectFieldRef;
+use protobuf::reflect::ReflectValueRef;
+use protobuf::Message;
+
+use rocket::http::ContentType;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response;
+use rocket::response::content::Content;
+use rocket::response::Responder;
+
+use serde_json::Map;
+use serde_json::Value;
+
+// Encodes the trains as a GTFS-realtime feed: a TripUpdate with the delays of
+// every stop and a VehiclePosition with the estimated position of every train.
+pub fn export_feed(
+    trains: &[(Trip, DelayMapTrain)],
+    realtime: &RealtimeSnapshot,
+    now: DateTime<Utc>,
+) -> FeedMessage {
+    let mut header = FeedHeader::new();
+    header.set_gtfs_realtime_version("2.0".to_string());
+    header.set_incrementality(FeedHeader_Incrementality::FULL_DATASET);
+    header.set_timestamp(now.timestamp() as u64);
+
+    let mut feed = FeedMessage::new();
+    feed.set_header(header);
+
+    for (trip, train) in trains {
+        let descriptor = trip_descriptor(trip, train, realtime, now);
+
+        let mut entity = FeedEntity::new();
+        entity.set_id(format!("{}-trip-update", trip.id));
+        entity.set_trip_update(trip_update(trip, train, descriptor.clone(), now));
+        feed.mut_entity().push(entity);
+
+        // A cancelled train is not riding anywhere
+        if !train.cancelled {
+            let mut entity = FeedEntity::new();
+            entity.set_id(format!("{}-vehicle", trip.id));
+            entity.set_vehicle(vehicle_position(trip, train, descriptor, now));
+            feed.mut_entity().push(entity);
+        }
+    }
+
+    feed
+}
+
+fn trip_descriptor(
+    trip: &Trip,
+    train: &DelayMapTrain,
+    realtime: &RealtimeSnapshot,
+    now: DateTime<Utc>,
+) -> TripDescriptor {
+    let added_trip = realtime.added_trips.get(&trip.id);
+    let start_date = realtime
+        .delays
+        .get(&trip.id)
+        .and_then(|trip_delay| trip_delay.start_date)
+        .or_else(|| added_trip.and_then(|added_trip| added_trip.start_date))
+        .unwrap_or_else(|| service_date_at(trip, now));
+
+    let mut descriptor = TripDescriptor::new();
+    descriptor.set_trip_id(trip.id.clone());
+    descriptor.set_route_id(trip.route_id.clone());
+    descriptor.set_start_date(start_date.format("%Y%m%d").to_string());
+    descriptor.set_schedule_relationship(if train.cancelled {
+        TripDescriptor_ScheduleRelationship::CANCELED
+    } else if added_trip.is_some() {
+        TripDescriptor_ScheduleRelationship::ADDED
+    } else {
+        TripDescriptor_ScheduleRelationship::SCHEDULED
+    });
+    descriptor
+}
+
+fn trip_update(
+    trip: &Trip,
+    train: &DelayMapTrain,
+    descriptor: TripDescriptor,
+    now: DateTime<Utc>,
+) -> TripUpdate {
+    let mut trip_update = TripUpdate::new();
+    trip_update.set_trip(descriptor);
+    trip_update.set_timestamp(now.timestamp() as u64);
+
+    if train.cancelled {
+        return trip_update;
+    }
+
+    let event = |delay: Option<i32>, time: Option<i64>| {
+        let mut event = TripUpdate_StopTimeEvent::new();
+        event.set_delay(delay?);
+        if let Some(time) = time {
+            event.set_time(time);
+        }
+        Some(event)
+    };
+
+    for (stop, stop_time) in train.stops.iter().zip(&trip.stop_times) {
+        let mut update = TripUpdate_StopTimeUpdate::new();
+        update.set_stop_sequence(u32::from(stop_time.stop_sequence));
+        update.set_stop_id(stop.stop_id.clone());
+        if stop.skipped {
+            update.set_schedule_relationship(
+                TripUpdate_StopTimeUpdate_ScheduleRelationship::SKIPPED,
+            );
+        } else {
+            if let Some(arrival) = event(stop.arrival_delay, stop.predicted_arrival) {
+                update.set_arrival(arrival);
+            }
+            if let Some(departure) = event(stop.departure_delay, stop.predicted_departure) {
+                update.set_departure(departure);
+            }
+        }
+        trip_update.mut_stop_time_update().push(update);
+    }
+
+    trip_update
+}
+
+fn vehicle_position(
+    trip: &Trip,
+    train: &DelayMapTrain,
+    descriptor: TripDescriptor,
+    now: DateTime<Utc>,
+) -> VehiclePosition {
+    let mut position = Position::new();
+    position.set_latitude(train.estimated_lat as f32);
+    position.set_longitude(train.estimated_lon as f32);
+    if let Some(bearing) = train.bearing {
+        position.set_bearing(bearing);
+    }
+    if let Some(speed) = train.speed {
+        position.set_speed(speed);
+    }
+
+    let mut vehicle_position = VehiclePosition::new();
+    vehicle_position.set_trip(descriptor);
+    vehicle_position.set_position(position);
+    vehicle_position.set_timestamp(now.timestamp() as u64);
+    vehicle_position.set_current_status(if train.is_stopped {
+        VehiclePosition_VehicleStopStatus::STOPPED_AT
+    } else {
+        VehiclePosition_VehicleStopStatus::IN_TRANSIT_TO
+    });
+    if let Some(stop_time) = trip.stop_times.get(train.stop_index) {
+        vehicle_position.set_current_stop_sequence(u32::from(stop_time.stop_sequence));
+        vehicle_position.set_stop_id(stop_time.stop.id.clone());
+    }
+    vehicle_position
+}
+
+// The message in the JSON mapping of protobuf: fields by their camelCase name,
+// enums by their name and 64-bit integers as strings. Fields that are not set
+// are left out.
+fn message_to_json(message: &dyn Message) -> Value {
+    let mut fields = Map::new();
+    for field in message.descriptor().fields() {
+        let value = match field.get_reflect(message) {
+            ReflectFieldRef::Optional(None) => continue,
+            ReflectFieldRef::Optional(Some(value)) => value_to_json(value),
+            ReflectFieldRef::Repeated(values) => {
+                if values.len() == 0 {
+                    continue;
+                }
+                Value::Array(values.into_iter().map(|x| value_to_json(x.as_ref())).collect())
+            }
+            ReflectFieldRef::Map(values) => Value::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| match value_to_json(key.as_ref()) {
+                        Value::String(key) => (key, value_to_json(value.as_ref())),
+                        key => (key.to_string(), value_to_json(value.as_ref())),
+                    })
+                    .collect(),
+            ),
+        };
+        fields.insert(field.json_name().to_string(), value);
+    }
+    Value::Object(fields)
+}
+
+fn value_to_json(value: ReflectValueRef) -> Value {
+    match value {
+        ReflectValueRef::U32(x) => Value::from(x),
+        ReflectValueRef::U64(x) => Value::from(x.to_string()),
+        ReflectValueRef::I32(x) => Value::from(x),
+        ReflectValueRef::I64(x) => Value::from(x.to_string()),
+        // NaN and infinity are not numbers in JSON
+        ReflectValueRef::F32(x) => serde_json::Number::from_f64(f64::from(x))
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        ReflectValueRef::F64(x) => serde_json::Number::from_f64(x)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        ReflectValueRef::Bool(x) => Value::from(x),
+        ReflectValueRef::String(x) => Value::from(x),
+        // GTFS-realtime has no bytes fields, this only keeps them readable
+        ReflectValueRef::Bytes(x) => Value::from(String::from_utf8_lossy(x).into_owned()),
+        ReflectValueRef::Enum(x) => Value::from(x.name()),
+        ReflectValueRef::Message(x) => message_to_json(x),
+    }
+}
+
+// A feed in the format given by the format url parameter: the binary protobuf
+// by default, or the protobuf text format or JSON for debugging
+pub struct FeedResponse {
+    feed: FeedMessage,
+    format: Option<String>,
+}
+
+impl FeedResponse {
+    pub fn new(feed: FeedMessage, format: Option<String>) -> Self {
+        FeedResponse { feed, format }
+    }
+}
+
+impl<'r> Responder<'r> for FeedResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self.format.as_deref() {
+            Some("text") => {
+                Content(ContentType::Plain, protobuf::text_format::print_to_string(&self.feed))
+                    .respond_to(request)
+            }
+            Some("json") => {
+                Content(ContentType::JSON, message_to_json(&self.feed).to_string())
+                    .respond_to(request)
+            }
+            _ => {
+                let bytes = self.feed
+                    .write_to_bytes()
+                    .map_err(|_| Status::InternalServerError)?;
+                Content(ContentType::new("application", "x-protobuf"), bytes).respond_to(request)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delay::Delay;
+    use crate::delay::TripDelay;
+
+    use gtfs_structures::Stop;
+    use gtfs_structures::StopTime;
+
+    use chrono::NaiveDate;
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Brussels;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+
+    // Current test setup, with the time being 12:00 in Brussels:
+    // Stop 1: departs at 11:30, (0, 0)
+    // Stop 2: arrives at 12:30, (1, 1), with 60 seconds of delay
+
+    #[test]
+    fn test_export() {
+        let realtime = create_realtime(false);
+        let feed = export_feed(&[create_train(&realtime)], &realtime, test_now());
+
+        assert_eq!(feed.get_header().get_gtfs_realtime_version(), "2.0");
+        assert_eq!(feed.get_header().get_timestamp(), test_now().timestamp() as u64);
+        assert_eq!(feed.get_entity().len(), 2);
+
+        let trip_update = feed.get_entity()[0].get_trip_update();
+        assert_eq!(trip_update.get_trip().get_trip_id(), "my-train");
+        assert_eq!(trip_update.get_trip().get_start_date(), "20220619");
+        assert_eq!(
+            trip_update.get_trip().get_schedule_relationship(),
+            TripDescriptor_ScheduleRelationship::SCHEDULED,
+        );
+        let stop_time_updates = trip_update.get_stop_time_update();
+        assert_eq!(stop_time_updates.len(), 2);
+        assert!(!stop_time_updates[0].has_arrival());
+        assert_eq!(stop_time_updates[0].get_departure().get_delay(), 0);
+        assert_eq!(stop_time_updates[1].get_stop_sequence(), 2);
+        assert_eq!(stop_time_updates[1].get_arrival().get_delay(), 60);
+        assert_eq!(
+            stop_time_updates[1].get_arrival().get_time(),
+            Brussels.ymd(2022, 6, 19).and_hms(12, 31, 0).timestamp(),
+        );
+
+        let vehicle_position = feed.get_entity()[1].get_vehicle();
+        assert_eq!(vehicle_position.get_trip().get_trip_id(), "my-train");
+        assert_eq!(vehicle_position.get_current_stop_sequence(), 2);
+        assert_eq!(
+            vehicle_position.get_current_status(),
+            VehiclePosition_VehicleStopStatus::IN_TRANSIT_TO,
+        );
+        assert!(vehicle_position.get_position().get_latitude() > 0.0);
+        assert!(vehicle_position.get_position().get_latitude() < 1.0);
+    }
+
+    #[test]
+    fn test_export_cancelled() {
+        let realtime = create_realtime(true);
+        let feed = export_feed(&[create_train(&realtime)], &realtime, test_now());
+
+        assert_eq!(feed.get_entity().len(), 1);
+        let trip_update = feed.get_entity()[0].get_trip_update();
+        assert_eq!(
+            trip_update.get_trip().get_schedule_relationship(),
+            TripDescriptor_ScheduleRelationship::CANCELED,
+        );
+        assert!(trip_update.get_stop_time_update().is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // The exported feed can be read back as a realtime feed
+        let realtime = create_realtime(false);
+        let feed = export_feed(&[create_train(&realtime)], &realtime, test_now());
+        let bytes = feed.write_to_bytes().unwrap();
+
+        let snapshot: RealtimeSnapshot = FeedMessage::parse_from_bytes(&bytes).unwrap().into();
+        let trip_delay = &snapshot.delays["my-train"];
+        assert_eq!(trip_delay.start_date, Some(NaiveDate::from_ymd(2022, 6, 19)));
+        assert_eq!(trip_delay.sequences[&2].arrival_delay, Some(60));
+        assert!(trip_delay.vehicle.is_some());
+    }
+
+    #[test]
+    fn test_json() {
+        let realtime = create_realtime(false);
+        let feed = export_feed(&[create_train(&realtime)], &realtime, test_now());
+        let json = message_to_json(&feed);
+
+        assert_eq!(json["header"]["gtfsRealtimeVersion"], "2.0");
+        assert_eq!(json["header"]["incrementality"], "FULL_DATASET");
+        assert_eq!(json["header"]["timestamp"], test_now().timestamp().to_string());
+        assert_eq!(json["entity"][0]["id"], "my-train-trip-update");
+        let trip_update = &json["entity"][0]["tripUpdate"];
+        assert_eq!(trip_update["trip"]["startDate"], "20220619");
+        assert_eq!(trip_update["stopTimeUpdate"][1]["arrival"]["delay"], 60);
+        // Fields that are not set are left out
+        assert!(json["entity"][0].get("vehicle").is_none());
+    }
+
+    // ----- HELPERS -----
+    fn test_now() -> DateTime<Utc> {
+        Brussels.ymd(2022, 6, 19).and_hms(12, 0, 0).with_timezone(&Utc)
+    }
+
+    fn create_realtime(cancelled: bool) -> RealtimeSnapshot {
+        let mut stops = HashMap::new();
+        stops.insert("stop2".to_string(), Delay {
+            arrival_delay: Some(60),
+            ..Default::default()
+        });
+
+        let mut realtime = RealtimeSnapshot::empty();
+        realtime.delays.insert("my-train".to_string(), TripDelay {
+            cancelled,
+            stops,
+            ..Default::default()
+        });
+        realtime
+    }
+
+    fn create_train(realtime: &RealtimeSnapshot) -> (Trip, DelayMapTrain) {
+        let mut trip = Trip::default();
+        trip.id = "my-train".to_string();
+        trip.route_id = "my-route".to_string();
+        for (i, (time, coordinate)) in [(11 * 3600 + 1800, 0.0), (12 * 3600 + 1800, 1.0)].iter().enumerate() {
+            let mut stop = Stop::default();
+            stop.id = format!("stop{}", i + 1);
+            stop.latitude = Some(*coordinate);
+            stop.longitude = Some(*coordinate);
+
+            let mut stop_time = StopTime::default();
+            stop_time.stop = Arc::new(stop);
+            stop_time.stop_sequence = i as u16 + 1;
+            if i == 0 {
+                stop_time.departure_time = Some(*time);
+            } else {
+                stop_time.arrival_time = Some(*time);
+            }
+            trip.stop_times.push(stop_time);
+        }
+
+        let train = DelayMapTrain::from_gtfs(&trip, &realtime.delays, None, test_now());
+        (trip, train)
+    }
+}
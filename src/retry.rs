@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+// Call f until it succeeds or all attempts are used up. The time waited
+// between two attempts starts at backoff and doubles after every failure.
+pub fn retry_with_backoff<T, E, F, S>(
+    attempts: u32,
+    backoff: Duration,
+    mut sleep: S,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+    S: FnMut(Duration),
+{
+    let mut wait = backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(_) => {
+                sleep(wait);
+                wait *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_try() {
+        let mut waits = vec![];
+        let res: Result<i32, ()> =
+            retry_with_backoff(3, Duration::from_secs(1), |d| waits.push(d), || Ok(42));
+        assert_eq!(res, Ok(42));
+        assert!(waits.is_empty());
+    }
+
+    #[test]
+    fn test_eventually_succeeds() {
+        let mut waits = vec![];
+        let mut calls = 0;
+        let res = retry_with_backoff(5, Duration::from_secs(1), |d| waits.push(d), || {
+            calls += 1;
+            if calls < 3 {
+                Err(calls)
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res, Ok(3));
+        assert_eq!(waits, vec![Duration::from_secs(1), Duration::from_secs(2)]);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let mut waits = vec![];
+        let mut calls = 0;
+        let res: Result<(), i32> =
+            retry_with_backoff(3, Duration::from_secs(10), |d| waits.push(d), || {
+                calls += 1;
+                Err(calls)
+            });
+        assert_eq!(res, Err(3));
+        assert_eq!(waits, vec![Duration::from_secs(10), Duration::from_secs(20)]);
+    }
+}
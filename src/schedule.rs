@@ -0,0 +1,236 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Timelike;
+use chrono::Utc;
+use chrono_tz::Europe::Brussels;
+
+use gtfs_structures::Exception;
+use gtfs_structures::Gtfs;
+use gtfs_structures::Trip;
+
+// Whether the trip is on the road at the given moment
+pub fn rides_now(gtfs: &Gtfs, trip: &Trip, now: DateTime<Utc>) -> bool {
+    let local_datetime = Brussels.from_utc_datetime(&now.naive_utc());
+    let local_date: NaiveDate = local_datetime.date().naive_local();
+    let local_timestamp = local_datetime.time().num_seconds_from_midnight();
+
+    let trip_starting_timestamp = trip.stop_times[0].departure_time;
+    let trip_ending_timestamp = trip.stop_times.last().unwrap().arrival_time;
+
+    // if it has no start or end timestamp, assume it doesn't ride
+    if trip_starting_timestamp.is_none() || trip_ending_timestamp.is_none() {
+        return false;
+    }
+
+    // If it rides today and at this time, it is currently on the road
+    if rides_at_date(gtfs, trip, local_date)
+        && trip_starting_timestamp.unwrap() <= local_timestamp
+        && trip_ending_timestamp.unwrap() >= local_timestamp
+    {
+        return true;
+    }
+
+    // If it rode yesterday, but after midnight it could still be on the road
+    if rides_at_date(gtfs, trip, local_date.pred())
+        && trip_ending_timestamp.unwrap() >= (24 * 60 * 60)
+    {
+        if trip_starting_timestamp.unwrap() < 24 * 60 * 60 {
+            return trip_starting_timestamp.unwrap() <= local_timestamp
+                || trip_ending_timestamp.unwrap() - 24 * 60 * 60 >= local_timestamp;
+        } else {
+            return trip_starting_timestamp.unwrap() - 24 * 60 * 60 <= local_timestamp
+                && trip_ending_timestamp.unwrap() - 24 * 60 * 60 >= local_timestamp;
+        }
+    }
+
+    return false;
+}
+
+pub fn rides_at_date(gtfs: &Gtfs, trip: &Trip, date: NaiveDate) -> bool {
+    let mut ret = false;
+
+    // Check if it rides in a normal schedule
+    if let Some(calendar) = gtfs.calendar.get(&trip.service_id) {
+        if calendar.start_date <= date && calendar.end_date >= date && calendar.valid_weekday(date)
+        {
+            ret = true;
+        }
+    }
+
+    // Check if there are exceptions today
+    for extra_day in gtfs
+        .calendar_dates
+        .get(&trip.service_id)
+        .iter()
+        .flat_map(|e| e.iter())
+    {
+        if extra_day.date == date {
+            if extra_day.exception_type == Exception::Added {
+                ret = true;
+            } else if extra_day.exception_type == Exception::Deleted {
+                ret = false;
+            }
+        }
+    }
+
+    ret
+}
+
+// The service day of the trip that is closest to the given moment. Trips
+// running past midnight belong to the day before, as long as they have not
+// arrived yet.
+pub fn service_date_at(trip: &Trip, now: DateTime<Utc>) -> NaiveDate {
+    let local_datetime = Brussels.from_utc_datetime(&now.naive_utc());
+    let local_date: NaiveDate = local_datetime.date().naive_local();
+    let local_timestamp = local_datetime.time().num_seconds_from_midnight();
+
+    let trip_starting_timestamp = trip.stop_times.first().and_then(|x| x.departure_time);
+    let trip_ending_timestamp = trip.stop_times.last().and_then(|x| x.arrival_time);
+
+    match (trip_starting_timestamp, trip_ending_timestamp) {
+        (Some(start), Some(end))
+            if local_timestamp < start && local_timestamp + 24 * 60 * 60 <= end =>
+        {
+            local_date.pred()
+        }
+        _ => local_date,
+    }
+}
+
+// GTFS times are relative to the start of the service day, which can be more
+// than 24 hours before the actual time for trips running past midnight. GTFS
+// defines it as noon minus 12 hours, which is not midnight on the days the
+// clocks change.
+pub fn service_day_start(date: NaiveDate) -> DateTime<Utc> {
+    (Brussels.from_local_date(&date).unwrap().and_hms(12, 0, 0) - Duration::hours(12))
+        .with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_structures::Calendar;
+    use gtfs_structures::StopTime;
+
+    use super::*;
+
+    // Current test setup:
+    // Trip "day" rides on weekdays of June 2022 from 13:00 to 14:00
+    // Trip "night" rides on weekdays of June 2022 from 23:30 to 00:30
+
+    #[test]
+    fn test_rides_now() {
+        let gtfs = create_gtfs();
+        let trip = &gtfs.trips["day"];
+        // Friday
+        assert!(rides_now(&gtfs, trip, brussels_time(17, 13, 30)));
+        assert!(!rides_now(&gtfs, trip, brussels_time(17, 12, 59)));
+        assert!(!rides_now(&gtfs, trip, brussels_time(17, 14, 1)));
+        // Saturday
+        assert!(!rides_now(&gtfs, trip, brussels_time(18, 13, 30)));
+    }
+
+    #[test]
+    fn test_rides_now_after_midnight() {
+        let gtfs = create_gtfs();
+        let trip = &gtfs.trips["night"];
+        // Friday evening, and the early hours of Saturday
+        assert!(rides_now(&gtfs, trip, brussels_time(17, 23, 45)));
+        assert!(rides_now(&gtfs, trip, brussels_time(18, 0, 15)));
+        assert!(!rides_now(&gtfs, trip, brussels_time(18, 0, 45)));
+        // Sunday after midnight, but the trip did not ride on Saturday
+        assert!(!rides_now(&gtfs, trip, brussels_time(19, 0, 15)));
+    }
+
+    #[test]
+    fn test_rides_at_date() {
+        let gtfs = create_gtfs();
+        let trip = &gtfs.trips["day"];
+        assert!(rides_at_date(&gtfs, trip, NaiveDate::from_ymd(2022, 6, 17)));
+        assert!(!rides_at_date(&gtfs, trip, NaiveDate::from_ymd(2022, 6, 18)));
+        assert!(!rides_at_date(&gtfs, trip, NaiveDate::from_ymd(2022, 7, 1)));
+    }
+
+    #[test]
+    fn test_service_date_at() {
+        let gtfs = create_gtfs();
+        let day = &gtfs.trips["day"];
+        let night = &gtfs.trips["night"];
+        let saturday = NaiveDate::from_ymd(2022, 6, 18);
+        assert_eq!(service_date_at(day, brussels_time(18, 0, 15)), saturday);
+        assert_eq!(service_date_at(night, brussels_time(18, 23, 45)), saturday);
+        assert_eq!(service_date_at(night, brussels_time(18, 0, 15)), saturday.pred());
+    }
+
+    #[test]
+    fn test_service_day_start() {
+        assert_eq!(
+            service_day_start(NaiveDate::from_ymd(2022, 6, 19)),
+            Utc.ymd(2022, 6, 18).and_hms(22, 0, 0),
+        );
+    }
+
+    #[test]
+    fn test_service_day_start_dst() {
+        // Clocks go forward: 23:00 the day before in local time
+        assert_eq!(
+            service_day_start(NaiveDate::from_ymd(2022, 3, 27)),
+            Utc.ymd(2022, 3, 26).and_hms(22, 0, 0),
+        );
+        // Clocks go back: 01:00 in local time
+        assert_eq!(
+            service_day_start(NaiveDate::from_ymd(2022, 10, 30)),
+            Utc.ymd(2022, 10, 29).and_hms(23, 0, 0),
+        );
+    }
+
+    // ----- HELPERS -----
+    fn brussels_time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Brussels.ymd(2022, 6, day).and_hms(hour, minute, 0).with_timezone(&Utc)
+    }
+
+    fn create_stoptime(arrival_time: Option<u32>, departure_time: Option<u32>) -> StopTime {
+        let mut stoptime = StopTime::default();
+        stoptime.arrival_time = arrival_time;
+        stoptime.departure_time = departure_time;
+        stoptime
+    }
+
+    fn create_trip(id: &str, start: u32, end: u32) -> Trip {
+        let mut trip = Trip::default();
+        trip.id = id.to_string();
+        trip.service_id = "weekdays".to_string();
+        trip.stop_times = vec![
+            create_stoptime(None, Some(start)),
+            create_stoptime(Some(end), None),
+        ];
+        trip
+    }
+
+    fn create_gtfs() -> Gtfs {
+        let mut gtfs = Gtfs::default();
+
+        gtfs.calendar.insert("weekdays".to_string(), Calendar {
+            id: "weekdays".to_string(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date: NaiveDate::from_ymd(2022, 6, 1),
+            end_date: NaiveDate::from_ymd(2022, 6, 30),
+        });
+
+        for trip in [
+            create_trip("day", 13 * 60 * 60, 14 * 60 * 60),
+            create_trip("night", 23 * 60 * 60 + 30 * 60, 24 * 60 * 60 + 30 * 60),
+        ] {
+            gtfs.trips.insert(trip.id.clone(), trip);
+        }
+
+        gtfs
+    }
+}
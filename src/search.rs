@@ -0,0 +1,108 @@
+// Lowercases the text and removes accents, so "Liège" matches "liege"
+pub fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ä' | 'ã' => 'a',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            '-' | '\'' | '/' => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// How well the name matches the query, lower is better. None if it doesn't
+// match at all. Small typos are allowed, as long as they are in a word the
+// query starts with.
+pub fn search_score(name: &str, query: &str) -> Option<u32> {
+    let name = normalize(name);
+    let query = normalize(query.trim());
+
+    if name.starts_with(&query) {
+        return Some(0);
+    }
+    if name.contains(&query) {
+        return Some(1);
+    }
+
+    let query_chars: Vec<char> = query.chars().collect();
+    let max_typos = (query_chars.len() / 4).max(1);
+    let matches_word = name.split(' ').any(|word| {
+        let word_chars: Vec<char> = word.chars().collect();
+        // Compare with prefixes around the length of the query, so missing
+        // or extra characters are also counted as a single typo
+        let shortest = query_chars.len().saturating_sub(max_typos);
+        let longest = (query_chars.len() + max_typos).min(word_chars.len());
+        (shortest..=longest).any(|end| levenshtein(&query_chars, &word_chars[..end]) <= max_typos)
+    });
+
+    if query_chars.len() >= 3 && matches_word {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Liège-Guillemins"), "liege guillemins");
+        assert_eq!(normalize("BRUSSEL-ZUID"), "brussel zuid");
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        let a: Vec<char> = "kitten".chars().collect();
+        let b: Vec<char> = "sitting".chars().collect();
+        assert_eq!(levenshtein(&a, &b), 3);
+        assert_eq!(levenshtein(&a, &a), 0);
+        assert_eq!(levenshtein(&a, &[]), 6);
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(search_score("Gent-Sint-Pieters", "gent"), Some(0));
+        assert_eq!(search_score("Liège-Guillemins", "liege"), Some(0));
+    }
+
+    #[test]
+    fn test_substring() {
+        assert_eq!(search_score("Gent-Sint-Pieters", "pieters"), Some(1));
+        assert_eq!(search_score("Brussel-Zuid", "zuid"), Some(1));
+    }
+
+    #[test]
+    fn test_typo() {
+        assert_eq!(search_score("Brussel-Zuid", "brusel"), Some(2));
+        assert_eq!(search_score("Oostende", "oostnde"), Some(2));
+        assert_eq!(search_score("Gent-Sint-Pieters", "peters"), Some(2));
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert_eq!(search_score("Gent-Sint-Pieters", "antwerpen"), None);
+        // Short queries need to match exactly
+        assert_eq!(search_score("Gent-Sint-Pieters", "xe"), None);
+    }
+}
@@ -0,0 +1,312 @@
+use gtfs_structures::Gtfs;
+use gtfs_structures::Shape;
+use gtfs_structures::Trip;
+
+use std::collections::HashMap;
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// The distance between two coordinates in meters
+pub fn haversine((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+// A shape from shapes.txt as a polyline, so positions can be expressed as the
+// distance (in meters) along it.
+#[derive(Debug, Clone)]
+pub struct ShapeLine {
+    points: Vec<(f64, f64)>,
+    // The distance from the start of the shape to each point
+    distances: Vec<f64>,
+    // The shape_dist_traveled of each point, in the units of the feed
+    traveled: Vec<Option<f32>>,
+}
+
+impl ShapeLine {
+    // None if the shape has too few points to be a line
+    pub fn new(shape: &[Shape]) -> Option<ShapeLine> {
+        let mut shape: Vec<&Shape> = shape.iter().collect();
+        shape.sort_by_key(|point| point.sequence);
+
+        ShapeLine::build(
+            shape.iter().map(|point| (point.latitude, point.longitude)).collect(),
+            shape.iter().map(|point| point.dist_traveled).collect(),
+        )
+    }
+
+    // A line straight through the given coordinates, for trips without a shape
+    pub fn from_points(points: Vec<(f64, f64)>) -> Option<ShapeLine> {
+        let traveled = vec![None; points.len()];
+        ShapeLine::build(points, traveled)
+    }
+
+    fn build(points: Vec<(f64, f64)>, traveled: Vec<Option<f32>>) -> Option<ShapeLine> {
+        if points.len() < 2 {
+            return None;
+        }
+
+        let mut distances = vec![0.0];
+        for window in points.windows(2) {
+            distances.push(distances.last().unwrap() + haversine(window[0], window[1]));
+        }
+
+        Some(ShapeLine {
+            points,
+            distances,
+            traveled,
+        })
+    }
+
+    pub fn length(&self) -> f64 {
+        *self.distances.last().unwrap()
+    }
+
+    // The distance along the shape of a stop. If the feed gives how far along
+    // the shape the stop is, that is used. Otherwise the stop is projected on
+    // the closest part of the shape after from, so stops that are visited
+    // twice are found in the right order.
+    pub fn locate(&self, (lat, lon): (f64, f64), dist_traveled: Option<f32>, from: f64) -> f64 {
+        if let Some(distance) = dist_traveled.and_then(|x| self.locate_traveled(x)) {
+            return distance;
+        }
+
+        let mut best: Option<(f64, f64)> = None;
+        for i in 0..self.points.len() - 1 {
+            if self.distances[i + 1] < from {
+                continue;
+            }
+            let (start, end) = (self.points[i], self.points[i + 1]);
+            let length = self.distances[i + 1] - self.distances[i];
+            // Only the part of the segment after from can be used
+            let t_min = if length > 0.0 {
+                ((from - self.distances[i]) / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = project(start, end, (lat, lon)).max(t_min);
+            let offset = haversine(lerp(start, end, t), (lat, lon));
+            if best.map(|(best_offset, _)| offset < best_offset).unwrap_or(true) {
+                best = Some((offset, self.distances[i] + t * length));
+            }
+        }
+
+        best.map(|(_, distance)| distance).unwrap_or(from)
+    }
+
+    fn locate_traveled(&self, dist_traveled: f32) -> Option<f64> {
+        for i in 0..self.points.len() - 1 {
+            if let (Some(start), Some(end)) = (self.traveled[i], self.traveled[i + 1]) {
+                if start <= dist_traveled && dist_traveled <= end {
+                    let t = if end > start {
+                        f64::from((dist_traveled - start) / (end - start))
+                    } else {
+                        0.0
+                    };
+                    return Some(self.distances[i] + t * (self.distances[i + 1] - self.distances[i]));
+                }
+            }
+        }
+        None
+    }
+
+    // The coordinates at the given distance along the shape
+    pub fn point_at(&self, distance: f64) -> (f64, f64) {
+        let i = self.segment_at(distance);
+        let length = self.distances[i + 1] - self.distances[i];
+        let t = if length > 0.0 {
+            ((distance - self.distances[i]) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        lerp(self.points[i], self.points[i + 1], t)
+    }
+
+    // The part of the shape between two distances along it
+    pub fn slice(&self, from: f64, to: f64) -> Vec<(f64, f64)> {
+        let mut points = vec![self.point_at(from)];
+        for (i, point) in self.points.iter().enumerate() {
+            if self.distances[i] > from && self.distances[i] < to {
+                points.push(*point);
+            }
+        }
+        points.push(self.point_at(to));
+        points
+    }
+
+    // The index of the segment containing the distance
+    fn segment_at(&self, distance: f64) -> usize {
+        (0..self.points.len() - 1)
+            .find(|&i| distance <= self.distances[i + 1])
+            .unwrap_or(self.points.len() - 2)
+    }
+}
+
+// The shapes of the static feed by id. Building them takes a while, so this is
+// only done once for every feed that is loaded.
+pub fn shape_lines(gtfs: &Gtfs) -> HashMap<String, ShapeLine> {
+    gtfs.shapes
+        .iter()
+        .filter_map(|(id, shape)| Some((id.clone(), ShapeLine::new(shape)?)))
+        .collect()
+}
+
+// The shape of the trip, if the static feed has one
+pub fn trip_shape<'a>(shapes: &'a HashMap<String, ShapeLine>, trip: &Trip) -> Option<&'a ShapeLine> {
+    shapes.get(trip.shape_id.as_ref()?)
+}
+
+fn lerp(start: (f64, f64), end: (f64, f64), t: f64) -> (f64, f64) {
+    (start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1))
+}
+
+// How far along the segment the closest point to the given point is, between
+// 0 and 1. Longitudes are scaled so the small distances are roughly correct.
+fn project(start: (f64, f64), end: (f64, f64), point: (f64, f64)) -> f64 {
+    let scale = start.0.to_radians().cos();
+    let (dx, dy) = ((end.1 - start.1) * scale, end.0 - start.0);
+    let (px, py) = ((point.1 - start.1) * scale, point.0 - start.0);
+    let length = dx * dx + dy * dy;
+    if length == 0.0 {
+        0.0
+    } else {
+        ((px * dx + py * dy) / length).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Current test setup: an L-shaped line, going north and then east
+    // (0, 0) -> (0.01, 0) -> (0.01, 0.01)
+
+    #[test]
+    fn test_haversine() {
+        // Brussel-Zuid to Gent-Sint-Pieters is about 50 kilometers
+        let distance = haversine((50.8357, 4.3365), (51.0359, 3.7108));
+        assert!((distance - 49_000.0).abs() < 1_000.0);
+        assert_eq!(haversine((51.0, 4.0), (51.0, 4.0)), 0.0);
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(ShapeLine::new(&[]).is_none());
+        assert!(ShapeLine::new(&[create_point(0, 0.0, 0.0, None)]).is_none());
+
+        let line = create_line(None);
+        assert!((line.length() - 2.0 * haversine((0.0, 0.0), (0.01, 0.0))).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_from_points() {
+        assert!(ShapeLine::from_points(vec![(0.0, 0.0)]).is_none());
+
+        let line = ShapeLine::from_points(vec![(0.0, 0.0), (0.01, 0.0), (0.01, 0.01)]).unwrap();
+        assert!((line.length() - create_line(None).length()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_point_at() {
+        let line = create_line(None);
+        let half = line.length() / 2.0;
+        assert_close(line.point_at(0.0), (0.0, 0.0));
+        assert_close(line.point_at(half), (0.01, 0.0));
+        assert_close(line.point_at(half * 1.5), (0.01, 0.005));
+        assert_close(line.point_at(line.length() * 2.0), (0.01, 0.01));
+    }
+
+    #[test]
+    fn test_locate() {
+        let line = create_line(None);
+        let half = line.length() / 2.0;
+        // Slightly next to the line
+        assert!((line.locate((0.005, 0.0001), None, 0.0) - half / 2.0).abs() < 1.0);
+        assert!((line.locate((0.0101, 0.01), None, 0.0) - line.length()).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_locate_from() {
+        // A line going east and back west, passing (0, 0.005) twice
+        let line = ShapeLine::new(&[
+            create_point(1, 0.0, 0.0, None),
+            create_point(2, 0.0, 0.01, None),
+            create_point(3, 0.0, 0.0, None),
+        ]).unwrap();
+        let quarter = line.length() / 4.0;
+        assert!((line.locate((0.0, 0.005), None, 0.0) - quarter).abs() < 1.0);
+        assert!((line.locate((0.0, 0.005), None, 2.0 * quarter) - 3.0 * quarter).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_locate_traveled() {
+        let line = create_line(Some(10.0));
+        let half = line.length() / 2.0;
+        // The coordinates are ignored when the distance traveled is known
+        assert!((line.locate((0.0, 0.0), Some(15.0), 0.0) - half * 1.5).abs() < 1.0);
+        // Unless it is not on the shape
+        assert!((line.locate((0.0, 0.0), Some(50.0), 0.0)).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_slice() {
+        let line = create_line(None);
+        let half = line.length() / 2.0;
+        let points = line.slice(half / 2.0, half * 1.5);
+        assert_eq!(points.len(), 3);
+        assert_close(points[0], (0.005, 0.0));
+        assert_close(points[1], (0.01, 0.0));
+        assert_close(points[2], (0.01, 0.005));
+    }
+
+    #[test]
+    fn test_shape_lines() {
+        let mut gtfs = Gtfs::default();
+        gtfs.shapes.insert("line".to_string(), vec![
+            create_point(1, 0.0, 0.0, None),
+            create_point(2, 0.01, 0.0, None),
+        ]);
+        gtfs.shapes.insert("point".to_string(), vec![create_point(1, 0.0, 0.0, None)]);
+        let shapes = shape_lines(&gtfs);
+        assert_eq!(shapes.len(), 1);
+
+        let mut trip = Trip::default();
+        assert!(trip_shape(&shapes, &trip).is_none());
+        trip.shape_id = Some("line".to_string());
+        assert!(trip_shape(&shapes, &trip).is_some());
+        trip.shape_id = Some("point".to_string());
+        assert!(trip_shape(&shapes, &trip).is_none());
+    }
+
+    // ----- HELPERS -----
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-6 && (actual.1 - expected.1).abs() < 1e-6,
+            "{:?} != {:?}",
+            actual,
+            expected,
+        );
+    }
+
+    fn create_point(sequence: usize, lat: f64, lon: f64, dist_traveled: Option<f32>) -> Shape {
+        Shape {
+            id: "shape".to_string(),
+            latitude: lat,
+            longitude: lon,
+            sequence,
+            dist_traveled,
+        }
+    }
+
+    // The points are given out of order, as they can be in shapes.txt
+    fn create_line(step: Option<f32>) -> ShapeLine {
+        ShapeLine::new(&[
+            create_point(2, 0.01, 0.0, step),
+            create_point(1, 0.0, 0.0, step.map(|_| 0.0)),
+            create_point(3, 0.01, 0.01, step.map(|x| 2.0 * x)),
+        ]).unwrap()
+    }
+}
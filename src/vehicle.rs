@@ -0,0 +1,197 @@
+use crate::delaymap_occupancy::DelayMapCarriage;
+use crate::delaymap_occupancy::DelayMapOccupancy;
+use crate::gtfs_realtime::VehiclePosition;
+use crate::gtfs_realtime::VehiclePosition_VehicleStopStatus;
+
+use gtfs_structures::StopTime;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+// Positions older than this (in seconds) are not used anymore
+const MAX_POSITION_AGE: i64 = 5 * 60;
+
+// The position of a train as reported in the realtime feed
+#[derive(Debug, Clone, Default)]
+pub struct Vehicle {
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    // In degrees, clockwise from north
+    pub bearing: Option<f32>,
+    // In meters per second
+    pub speed: Option<f32>,
+    pub stopped: bool,
+    pub current_stop_sequence: Option<u32>,
+    pub stop_id: Option<String>,
+    // In seconds since the epoch
+    pub timestamp: Option<u64>,
+    pub occupancy: Option<DelayMapOccupancy>,
+    pub carriages: Vec<DelayMapCarriage>,
+}
+
+impl From<VehiclePosition> for Vehicle {
+    fn from(vehicle_position: VehiclePosition) -> Vehicle {
+        let position = vehicle_position.position.as_ref();
+        Vehicle {
+            lat: position.filter(|x| x.has_latitude()).map(|x| f64::from(x.get_latitude())),
+            lon: position.filter(|x| x.has_longitude()).map(|x| f64::from(x.get_longitude())),
+            bearing: position.filter(|x| x.has_bearing()).map(|x| x.get_bearing()),
+            speed: position.filter(|x| x.has_speed()).map(|x| x.get_speed()),
+            // Without a status, the train is in transit
+            stopped: vehicle_position.get_current_status()
+                == VehiclePosition_VehicleStopStatus::STOPPED_AT,
+            current_stop_sequence: if vehicle_position.has_current_stop_sequence() {
+                Some(vehicle_position.get_current_stop_sequence())
+            } else {
+                None
+            },
+            stop_id: if vehicle_position.has_stop_id() {
+                Some(vehicle_position.get_stop_id().to_string())
+            } else {
+                None
+            },
+            timestamp: if vehicle_position.has_timestamp() {
+                Some(vehicle_position.get_timestamp())
+            } else {
+                None
+            },
+            occupancy: DelayMapOccupancy::from_vehicle(&vehicle_position),
+            carriages: vehicle_position
+                .get_multi_carriage_details()
+                .iter()
+                .map(|carriage| carriage.into())
+                .collect(),
+        }
+    }
+}
+
+impl Vehicle {
+    // Whether the data of the vehicle is recent enough to be used at the given
+    // moment. Without a timestamp, there is no way to tell.
+    pub fn is_recent(&self, now: DateTime<Utc>) -> bool {
+        self.timestamp
+            .map(|timestamp| (now.timestamp() - timestamp as i64).abs() <= MAX_POSITION_AGE)
+            .unwrap_or(false)
+    }
+
+    // The position, if it is known and recent enough to be used at the given
+    // moment
+    pub fn position_at(&self, now: DateTime<Utc>) -> Option<(f64, f64)> {
+        if !self.is_recent(now) {
+            return None;
+        }
+        Some((self.lat?, self.lon?))
+    }
+
+    // The index of the stop the train is at, or is riding towards
+    pub fn stop_index(&self, stop_times: &[StopTime]) -> Option<usize> {
+        if let Some(stop_sequence) = self.current_stop_sequence {
+            stop_times
+                .iter()
+                .position(|stop_time| u32::from(stop_time.stop_sequence) == stop_sequence)
+        } else {
+            let stop_id = self.stop_id.as_ref()?;
+            stop_times.iter().position(|stop_time| &stop_time.stop.id == stop_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs_realtime::Position;
+    use crate::gtfs_realtime::VehiclePosition_CarriageDetails;
+
+    use gtfs_structures::Stop;
+
+    use chrono::TimeZone;
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_from_empty() {
+        let vehicle = Vehicle::from(VehiclePosition::new());
+        assert_eq!(vehicle.lat, None);
+        assert_eq!(vehicle.lon, None);
+        assert_eq!(vehicle.bearing, None);
+        assert!(!vehicle.stopped);
+        assert_eq!(vehicle.current_stop_sequence, None);
+        assert_eq!(vehicle.occupancy, None);
+        assert!(vehicle.carriages.is_empty());
+    }
+
+    #[test]
+    fn test_from_full() {
+        let vehicle = create_vehicle();
+        assert_eq!(vehicle.lat, Some(51.0));
+        assert_eq!(vehicle.lon, Some(3.5));
+        assert_eq!(vehicle.bearing, Some(90.0));
+        assert_eq!(vehicle.speed, Some(25.0));
+        assert!(vehicle.stopped);
+        assert_eq!(vehicle.current_stop_sequence, Some(2));
+        assert_eq!(vehicle.timestamp, Some(1655647200));
+        assert_eq!(vehicle.occupancy.unwrap().percentage, Some(40));
+        assert_eq!(vehicle.carriages.len(), 2);
+        assert_eq!(vehicle.carriages[1].sequence, Some(2));
+    }
+
+    #[test]
+    fn test_position_at() {
+        let vehicle = create_vehicle();
+        assert_eq!(vehicle.position_at(Utc.timestamp(1655647260, 0)), Some((51.0, 3.5)));
+        assert_eq!(vehicle.position_at(Utc.timestamp(1655647200 + 3600, 0)), None);
+        assert_eq!(Vehicle::default().position_at(Utc.timestamp(1655647200, 0)), None);
+
+        // Without a timestamp, the position could be of any moment
+        let mut vehicle = create_vehicle();
+        vehicle.timestamp = None;
+        assert_eq!(vehicle.position_at(Utc.timestamp(1655647260, 0)), None);
+    }
+
+    #[test]
+    fn test_stop_index() {
+        let stop_times: Vec<StopTime> = vec!["stop1", "stop2", "stop3"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, stop_id)| {
+                let mut stop = Stop::default();
+                stop.id = stop_id.to_string();
+                let mut stop_time = StopTime::default();
+                stop_time.stop = Arc::new(stop);
+                stop_time.stop_sequence = i as u16 + 1;
+                stop_time
+            })
+            .collect();
+
+        assert_eq!(create_vehicle().stop_index(&stop_times), Some(1));
+
+        let mut vehicle = Vehicle::default();
+        vehicle.stop_id = Some("stop3".to_string());
+        assert_eq!(vehicle.stop_index(&stop_times), Some(2));
+        assert_eq!(Vehicle::default().stop_index(&stop_times), None);
+    }
+
+    // ----- HELPERS -----
+    fn create_vehicle() -> Vehicle {
+        let mut position = Position::new();
+        position.set_latitude(51.0);
+        position.set_longitude(3.5);
+        position.set_bearing(90.0);
+        position.set_speed(25.0);
+
+        let mut vehicle_position = VehiclePosition::new();
+        vehicle_position.set_position(position);
+        vehicle_position.set_current_status(VehiclePosition_VehicleStopStatus::STOPPED_AT);
+        vehicle_position.set_current_stop_sequence(2);
+        vehicle_position.set_timestamp(1655647200);
+        vehicle_position.set_occupancy_percentage(40);
+        for carriage_sequence in 1..=2 {
+            let mut carriage = VehiclePosition_CarriageDetails::new();
+            carriage.set_carriage_sequence(carriage_sequence);
+            vehicle_position.mut_multi_carriage_details().push(carriage);
+        }
+
+        vehicle_position.into()
+    }
+}